@@ -0,0 +1,168 @@
+//! Synchronous wrappers around [`Schema`] for callers that are not async.
+//!
+//! Every call is driven to completion on an internal current-thread tokio runtime,
+//! so these types must not be used from inside another tokio runtime.
+
+use std::collections::HashMap;
+
+use tokio::runtime::Runtime;
+
+use crate::{
+    Result,
+    http::{HttpClient, HttpRequest},
+    schema::{
+        BookInfo, ChapterCommand, Command, CommandWithSession, PageItems, PageParams, Paginated,
+        Schema, SearchCommand, Session, TocCommand,
+    },
+};
+
+#[derive(Debug)]
+pub struct BlockingSchema<'s> {
+    schema: &'s Schema,
+    runtime: Runtime,
+}
+
+impl<'s> BlockingSchema<'s> {
+    pub fn new(schema: &'s Schema) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self { schema, runtime })
+    }
+
+    pub fn schema(&self) -> &'s Schema {
+        self.schema
+    }
+
+    pub fn search<'a, 'b, 'c>(
+        &'a self,
+        keyword: &'b str,
+        http: &'c HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> BlockingPageItems<'a, 'b, 'c, CommandWithSession<'s, 's, SearchCommand>> {
+        BlockingPageItems::new(
+            &self.runtime,
+            self.schema.search(keyword, http, session, extra_headers),
+        )
+    }
+
+    pub fn book_info(
+        &self,
+        id: &str,
+        http: &HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> Result<Option<BookInfo>> {
+        self.runtime.block_on(self.schema.book_info(id, http, session, extra_headers))
+    }
+
+    pub fn chapter<'a, 'b, 'c>(
+        &'a self,
+        id: &'b str,
+        http: &'c HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> BlockingPageItems<'a, 'b, 'c, CommandWithSession<'s, 's, ChapterCommand>> {
+        BlockingPageItems::new(&self.runtime, self.schema.chapter(id, http, session, extra_headers))
+    }
+
+    pub fn toc<'a, 'b, 'c>(
+        &'a self,
+        id: &'b str,
+        http: &'c HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> BlockingPageItems<'a, 'b, 'c, CommandWithSession<'s, 's, TocCommand>> {
+        BlockingPageItems::new(&self.runtime, self.schema.toc(id, http, session, extra_headers))
+    }
+}
+
+pub struct BlockingPageItems<'r, 'a, 'b, C> {
+    runtime: &'r Runtime,
+    items: PageItems<'a, 'b, C>,
+}
+
+impl<'r, 'a, 'b, C> BlockingPageItems<'r, 'a, 'b, C> {
+    fn new(runtime: &'r Runtime, items: PageItems<'a, 'b, C>) -> Self {
+        Self { runtime, items }
+    }
+}
+
+impl<C> BlockingPageItems<'_, '_, '_, C>
+where
+    C: Command<RequestParams = PageParams, Request = Option<HttpRequest>, Page = String>,
+    C::PageContent: Paginated,
+{
+    pub fn next_page(&mut self) -> Result<Option<C::PageContent>> {
+        self.runtime.block_on(self.items.next_page())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hashset, tests::mock_url};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    #[test]
+    fn test_blocking_book_info() {
+        // the blocking calls must not run inside a runtime, so the server gets its own
+        let server_runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = server_runtime.block_on(async {
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .and(path("/123"))
+                .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+                .expect(1)
+                .mount(&server)
+                .await;
+            server
+        });
+        let runtime = crate::runtime::Runtime::new();
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function search()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    return {
+        title = "title",
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+end
+local function chapter()
+end
+local function toc()
+end
+return {
+    search = {page = search, parse = search},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = chapter, parse = chapter},
+    toc = {page = toc, parse = toc},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let blocking = BlockingSchema::new(&schema).unwrap();
+        let info = blocking.book_info("123", &http, None, None).unwrap().unwrap();
+        assert_eq!(info.title, "title");
+        assert_eq!(info.intro, "intro");
+        drop(http);
+        server_runtime.block_on(server.verify());
+    }
+}
@@ -3,6 +3,10 @@ pub enum Error {
     #[error("Lua error: {0}")]
     LuaError(#[from] mlua::Error),
 
+    /// The schema script failed to compile or to evaluate when it was loaded.
+    #[error("Lua load error: {0}")]
+    LuaLoadError(mlua::Error),
+
     #[error("Script parsing error: {0}")]
     ScriptParseError(String),
 
@@ -11,6 +15,96 @@ pub enum Error {
 
     #[error("Schema error: {0}")]
     SchemaError(#[from] SchemaError),
+
+    #[error("Schema id mismatch: expected {expected}, found {found}")]
+    SchemaIdMismatch {
+        expected: uuid::Uuid,
+        found: uuid::Uuid,
+    },
+
+    /// A schema with the same id is already registered.
+    #[error("Duplicate schema id: {0}")]
+    DuplicateId(uuid::Uuid),
+
+    /// The schema declares `--@requires-session` but no session was supplied.
+    #[error("The schema requires a session")]
+    SessionRequired,
+
+    #[error("Operation timed out")]
+    Timeout,
+
+    /// The host cancelled the operation before it completed.
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    /// A script exceeded a limit on the resources it may use.
+    #[error("Resource limit exceeded: {0}")]
+    ResourceLimit(String),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+impl Error {
+    /// A stable code for the kind of the error, for hosts that can not rely on messages.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::LuaError(_) => ErrorCode::LuaRuntime,
+            Error::LuaLoadError(_) | Error::ScriptParseError(_) => ErrorCode::ScriptParse,
+            Error::NetworkError(e) if e.is_timeout() => ErrorCode::Timeout,
+            Error::NetworkError(e) if e.status().is_some() => ErrorCode::HttpStatus,
+            Error::NetworkError(_) => ErrorCode::Network,
+            Error::SchemaError(e) => e.code(),
+            Error::SchemaIdMismatch { .. } => ErrorCode::SchemaIdMismatch,
+            Error::DuplicateId(_) => ErrorCode::DuplicateId,
+            Error::SessionRequired => ErrorCode::SessionRequired,
+            Error::Timeout => ErrorCode::Timeout,
+            Error::Cancelled => ErrorCode::Cancelled,
+            Error::ResourceLimit(_) => ErrorCode::ResourceLimit,
+            Error::IoError(_) => ErrorCode::Io,
+        }
+    }
+
+    fn lua_error(&self) -> Option<&mlua::Error> {
+        match self {
+            Error::LuaError(e) | Error::LuaLoadError(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The message of the underlying Lua error, without context or traceback.
+    fn lua_message(&self) -> Option<&str> {
+        let mut error = self.lua_error()?;
+        loop {
+            match error {
+                mlua::Error::RuntimeError(message)
+                | mlua::Error::SyntaxError { message, .. } => return Some(message),
+                mlua::Error::CallbackError { cause, .. }
+                | mlua::Error::WithContext { cause, .. } => error = cause.as_ref(),
+                _ => return None,
+            }
+        }
+    }
+
+    /// The Lua stack traceback of the error, if there is one.
+    pub fn lua_traceback(&self) -> Option<String> {
+        if let Some(mlua::Error::CallbackError { traceback, .. }) = self.lua_error() {
+            return Some(traceback.clone());
+        }
+        self.lua_message()?
+            .split_once("stack traceback:")
+            .map(|(_, traceback)| traceback.trim().to_string())
+    }
+
+    /// The line in the script where the Lua error happened, if it is known.
+    pub fn lua_line(&self) -> Option<u32> {
+        let message = self.lua_message()?;
+        let location = message.lines().next()?;
+        location
+            .split(':')
+            .skip(1)
+            .find_map(|part| part.parse().ok())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -23,6 +117,104 @@ pub enum SchemaError {
 
     #[error("Invalid url: {0}")]
     InvalidUrl(String),
+
+    #[error("Download budget exceeded")]
+    BudgetExceeded,
+
+    /// The body was still blank once the retries were used up, see
+    /// [`crate::http::HttpClient::with_retry_on_empty_body`].
+    #[error("Empty response")]
+    EmptyResponse,
+
+    /// The response has a status the client does not accept, see
+    /// [`crate::http::HttpClient::with_success_statuses`].
+    #[error("Unexpected status: {0}")]
+    UnexpectedStatus(u16),
+
+    /// A response body is longer than the client accepts, in bytes.
+    #[error("Response body larger than {0} bytes")]
+    BodyTooLarge(usize),
+
+    #[error("Unexpected content type: expected {expected}, got {got}")]
+    UnexpectedContentType { expected: &'static str, got: String },
+
+    #[error("Invalid tls config: {0}")]
+    InvalidTlsConfig(String),
+
+    /// The server of a pinned domain presented a certificate that is not pinned.
+    #[error("Certificate of {0} does not match the pinned fingerprint")]
+    CertificateMismatch(String),
+}
+
+impl SchemaError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SchemaError::NotAllowedDomain(_) => ErrorCode::NotAllowedDomain,
+            SchemaError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            SchemaError::InvalidUrl(_) => ErrorCode::InvalidUrl,
+            SchemaError::UnexpectedStatus(_) => ErrorCode::HttpStatus,
+            SchemaError::BudgetExceeded | SchemaError::BodyTooLarge(_) => ErrorCode::ResourceLimit,
+            SchemaError::UnexpectedContentType { .. } | SchemaError::EmptyResponse => {
+                ErrorCode::UnexpectedContent
+            }
+            SchemaError::InvalidTlsConfig(_) | SchemaError::CertificateMismatch(_) => {
+                ErrorCode::Tls
+            }
+        }
+    }
+}
+
+/// The kind of an [`Error`], see [`Error::code`].
+///
+/// The numeric values and the names returned by [`ErrorCode::as_str`] never change, so
+/// hosts may store them or map them to localized messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// the request could not be sent or its response could not be read
+    Network = 1,
+    Timeout = 2,
+    NotAllowedDomain = 3,
+    InvalidUrl = 4,
+    /// the schema script or its metadata is invalid
+    ScriptParse = 5,
+    /// a function of the schema failed when it was run
+    LuaRuntime = 6,
+    /// the server responded with an error status
+    HttpStatus = 7,
+    ResourceLimit = 8,
+    /// the host cancelled the operation
+    Cancelled = 9,
+    InvalidRequest = 10,
+    UnexpectedContent = 11,
+    Tls = 12,
+    SchemaIdMismatch = 13,
+    Io = 14,
+    DuplicateId = 15,
+    SessionRequired = 16,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Network => "network",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::NotAllowedDomain => "not_allowed_domain",
+            ErrorCode::InvalidUrl => "invalid_url",
+            ErrorCode::ScriptParse => "script_parse",
+            ErrorCode::LuaRuntime => "lua_runtime",
+            ErrorCode::HttpStatus => "http_status",
+            ErrorCode::ResourceLimit => "resource_limit",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::UnexpectedContent => "unexpected_content",
+            ErrorCode::Tls => "tls",
+            ErrorCode::SchemaIdMismatch => "schema_id_mismatch",
+            ErrorCode::Io => "io",
+            ErrorCode::DuplicateId => "duplicate_id",
+            ErrorCode::SessionRequired => "session_required",
+        }
+    }
 }
 
 pub type StdResult<T, E> = std::result::Result<T, E>;
@@ -30,3 +222,54 @@ pub type StdResult<T, E> = std::result::Result<T, E>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub type SchemaResult<T> = std::result::Result<T, SchemaError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers::method};
+
+    #[tokio::test]
+    async fn test_error_code() {
+        let cases = [
+            (
+                Error::LuaError(mlua::Error::RuntimeError("failed".to_string())),
+                ErrorCode::LuaRuntime,
+            ),
+            (Error::ScriptParseError("missing field: id".to_string()), ErrorCode::ScriptParse),
+            (Error::Timeout, ErrorCode::Timeout),
+            (Error::Cancelled, ErrorCode::Cancelled),
+            (Error::SessionRequired, ErrorCode::SessionRequired),
+            (Error::ResourceLimit("parse timeout".to_string()), ErrorCode::ResourceLimit),
+            (
+                SchemaError::NotAllowedDomain("example.com".to_string()).into(),
+                ErrorCode::NotAllowedDomain,
+            ),
+            (SchemaError::InvalidUrl("url".to_string()).into(), ErrorCode::InvalidUrl),
+            (SchemaError::BudgetExceeded.into(), ErrorCode::ResourceLimit),
+            (SchemaError::UnexpectedStatus(206).into(), ErrorCode::HttpStatus),
+            (std::io::Error::other("broken").into(), ErrorCode::Io),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.code(), code, "{}", error);
+        }
+
+        // nothing listens on port 1, so connecting fails
+        let refused = reqwest::get("http://localhost:1").await.unwrap_err();
+        assert_eq!(Error::from(refused).code(), ErrorCode::Network);
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let status = reqwest::get(crate::tests::mock_url(&server, "/"))
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap_err();
+        let error = Error::from(status);
+        assert_eq!(error.code(), ErrorCode::HttpStatus);
+        assert_eq!(error.code().as_str(), "http_status");
+        assert_eq!(error.code() as u16, 7);
+    }
+}
@@ -0,0 +1,60 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::Result;
+
+/// Write the items as newline-delimited json, flushing after every line so that the
+/// output can be streamed.
+///
+/// Works for anything serializable, e.g. [`SearchItem`](crate::schema::SearchItem),
+/// [`TocItem`](crate::schema::TocItem) and [`Paragraph`](crate::schema::Paragraph).
+pub fn write_jsonl<T, W>(items: impl IntoIterator<Item = T>, w: &mut W) -> Result<()>
+where
+    T: Serialize,
+    W: Write,
+{
+    for item in items {
+        serde_json::to_writer(&mut *w, &item).map_err(std::io::Error::from)?;
+        w.write_all(b"\n")?;
+        w.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Paragraph, SearchItem};
+
+    #[test]
+    fn test_write_jsonl() {
+        let item = |id: &str| SearchItem {
+            id: id.to_string(),
+            title: "title".to_string(),
+            author: "author".to_string(),
+            cover: "cover".to_string(),
+            last_update: "last_update".to_string(),
+            status: "status".to_string(),
+            intro: "intro".to_string(),
+            rating: None,
+            word_count: None,
+            latest_chapter: None,
+            snippet: None,
+            source: None,
+        };
+        let mut output = Vec::new();
+        write_jsonl([item("1"), item("2")], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["id"], "1");
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["id"], "2");
+
+        let mut output = Vec::new();
+        write_jsonl([Paragraph::Text("text".to_string())], &mut output).unwrap();
+        assert_eq!(output, b"{\"type\":\"text\",\"content\":\"text\"}\n");
+    }
+}
@@ -1,7 +1,22 @@
+use flate2::{Compression, write::GzEncoder};
 use serde::{Deserialize, Serialize};
 
 use crate::{Result, SchemaError, SchemaResult, StdResult};
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tls-pinning")]
+mod tls;
+
+#[cfg(feature = "tls-pinning")]
+pub use tls::TlsConfig;
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Method(reqwest::Method);
@@ -47,21 +62,354 @@ impl<'de> Deserialize<'de> for Method {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// An absent field, `nil` or `null` all take the default value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct HttpRequest {
     pub url: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub method: Method,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub headers: HashMap<String, String>,
-    #[serde(default)]
+    /// a string or an array of bytes
+    #[serde(default, deserialize_with = "deserialize_body")]
     pub body: Vec<u8>,
+    /// gzip the body and send it with `Content-Encoding: gzip`
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub compress_body: bool,
+    /// a json body, sent with `Content-Type: application/json` in place of `body`
+    #[serde(default)]
+    pub json: Option<serde_json::Value>,
+    /// whether the request is safe to retry, defaults to true only for GET, HEAD and OPTIONS
+    #[serde(default)]
+    pub idempotent: Option<bool>,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> StdResult<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+fn deserialize_body<'de, D>(deserializer: D) -> StdResult<Vec<u8>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct BodyVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for BodyVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a string or an array of bytes")
+        }
+
+        fn visit_unit<E: serde::de::Error>(self) -> StdResult<Vec<u8>, E> {
+            Ok(Vec::new())
+        }
+
+        fn visit_none<E: serde::de::Error>(self) -> StdResult<Vec<u8>, E> {
+            Ok(Vec::new())
+        }
+
+        fn visit_some<D2>(self, deserializer: D2) -> StdResult<Vec<u8>, D2::Error>
+        where
+            D2: serde::Deserializer<'de>,
+        {
+            deserializer.deserialize_any(self)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> StdResult<Vec<u8>, E> {
+            Ok(v.as_bytes().to_vec())
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> StdResult<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> StdResult<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> StdResult<Vec<u8>, A::Error>
+        where
+            A: serde::de::SeqAccess<'de>,
+        {
+            let mut body = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+            while let Some(byte) = seq.next_element()? {
+                body.push(byte);
+            }
+            Ok(body)
+        }
+
+        // an empty Lua table, as an empty body is passed to Lua, is not told apart from a map
+        fn visit_map<A>(self, mut map: A) -> StdResult<Vec<u8>, A::Error>
+        where
+            A: serde::de::MapAccess<'de>,
+        {
+            match map.next_key::<serde::de::IgnoredAny>()? {
+                None => Ok(Vec::new()),
+                Some(_) => Err(serde::de::Error::invalid_type(
+                    serde::de::Unexpected::Map,
+                    &self,
+                )),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(BodyVisitor)
+}
+
+impl HttpRequest {
+    pub fn is_idempotent(&self) -> bool {
+        self.idempotent.unwrap_or(matches!(
+            self.method.0,
+            reqwest::Method::GET | reqwest::Method::HEAD | reqwest::Method::OPTIONS
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// the canonical reason phrase of the status, e.g. `Not Found`
+    pub status_text: String,
+    /// e.g. `HTTP/1.1` or `HTTP/2.0`
+    pub http_version: String,
+    pub headers: HashMap<String, String>,
+    /// the url of the response after following redirects
+    pub final_url: String,
+    pub body: String,
+    /// the name of the encoding the body was decoded with
+    pub encoding: &'static str,
+}
+
+/// A limit on the total bytes downloaded, shared by every client it is cloned into.
+#[derive(Debug, Clone)]
+pub struct ByteBudget {
+    remaining: Arc<AtomicU64>,
+}
+
+impl ByteBudget {
+    pub fn new(bytes: u64) -> Self {
+        Self {
+            remaining: Arc::new(AtomicU64::new(bytes)),
+        }
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.remaining.load(Ordering::SeqCst)
+    }
+
+    fn consume(&self, bytes: u64) -> SchemaResult<()> {
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                remaining.checked_sub(bytes)
+            })
+            .map(|_| ())
+            .map_err(|_| {
+                self.remaining.store(0, Ordering::SeqCst);
+                SchemaError::BudgetExceeded
+            })
+    }
+}
+
+/// Retry idempotent requests that fail with a network error or a server error status.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// the delay before each retry
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, delay: Duration) -> Self {
+        Self { max_retries, delay }
+    }
+}
+
+/// Substitutes the host of outgoing urls, e.g. to route a blocked site to a mirror.
+///
+/// The rewritten url is the one checked against the legal domains, so the mirror has to
+/// be allowed too.
+#[derive(Debug, Clone, Default)]
+pub struct UrlRewriter {
+    hosts: HashMap<String, String>,
+}
+
+impl UrlRewriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send the requests for `host` to `mirror` instead.
+    pub fn with_host(mut self, host: impl Into<String>, mirror: impl Into<String>) -> Self {
+        self.hosts.insert(host.into(), mirror.into());
+        self
+    }
+
+    pub fn rewrite(&self, url: &mut reqwest::Url) -> SchemaResult<()> {
+        let Some(mirror) = url.host_str().and_then(|host| self.hosts.get(host)) else {
+            return Ok(());
+        };
+        url.set_host(Some(mirror))
+            .map_err(|e| SchemaError::InvalidUrl(format!("{} for mirror {}", e, mirror)))
+    }
+}
+
+/// The most urls whose validators are kept, the oldest are evicted beyond it.
+const MAX_VALIDATORS: usize = 1024;
+
+/// The most proxies whose clients are kept, the least recently used are evicted beyond it.
+const MAX_PROXY_CLIENTS: usize = 16;
+
+/// How long the client of a proxy is kept once it was last used.
+const PROXY_CLIENT_IDLE: Duration = Duration::from_secs(300);
+
+/// A client sending its requests through a proxy, see [`HttpClient::request_via_proxy`].
+#[derive(Debug)]
+struct ProxyClient {
+    client: reqwest::Client,
+    last_used: Instant,
+}
+
+/// The validators of a previous response, used for conditional requests.
+#[derive(Debug, Clone)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    stored_at: Instant,
 }
 
 #[derive(Debug)]
 pub struct HttpClient {
     client: reqwest::Client,
     allowed_domains: HashSet<String>,
+    budget: Option<ByteBudget>,
+    validators: Mutex<HashMap<String, Validators>>,
+    /// the clients of the proxies, by proxy url
+    proxy_clients: Mutex<HashMap<String, ProxyClient>>,
+    /// the warm-up pages, set once they have been requested
+    warmed_up: Mutex<HashMap<String, Arc<tokio::sync::OnceCell<()>>>>,
+    charset: Option<&'static encoding_rs::Encoding>,
+    retry: Option<RetryPolicy>,
+    read_timeout: Option<Duration>,
+    max_body_bytes: usize,
+    /// bodies shorter than this once trimmed are retried
+    min_body_len: Option<usize>,
+    /// the statuses accepted, 2xx unless more are added
+    success_statuses: HashSet<u16>,
+    rewriter: Option<UrlRewriter>,
+    #[cfg(feature = "tls-pinning")]
+    pinned_domains: HashSet<String>,
+}
+
+/// Builds an [`HttpClient`] together with the reqwest client it uses.
+#[derive(Debug, Default)]
+pub struct HttpClientBuilder {
+    allowed_domains: HashSet<String>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    retry: Option<RetryPolicy>,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    http1_only: bool,
+    user_agent: Option<String>,
+    #[cfg(feature = "tls-pinning")]
+    tls: Option<TlsConfig>,
+}
+
+impl HttpClientBuilder {
+    /// Fail fast when connecting to the host takes longer than the timeout.
+    ///
+    /// A connect timeout is a network error, so it is retried by the retry policy.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit each request, from sending it until the whole body is read.
+    ///
+    /// Like a connect timeout, it is retried by the retry policy, and every attempt gets
+    /// the full timeout.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Close pooled connections that have been idle for longer than the timeout.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Keep at most `max` idle connections per host in the pool.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Only speak HTTP/1.1, for sites that treat HTTP/2 clients differently.
+    ///
+    /// By default reqwest negotiates the version with the server.
+    pub fn http1_only(mut self) -> Self {
+        self.http1_only = true;
+        self
+    }
+
+    /// Send the `User-Agent`, e.g. `MyApp/1.0 (+https://example.com/contact)`, with every
+    /// request that does not set its own.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Verify certificates with the tls config, see [`TlsConfig`].
+    ///
+    /// A request to a pinned domain whose certificate does not match fails with
+    /// [`SchemaError::CertificateMismatch`].
+    #[cfg(feature = "tls-pinning")]
+    pub fn tls(mut self, tls: &TlsConfig) -> Self {
+        self.tls = Some(tls.clone());
+        self
+    }
+
+    pub fn build(self) -> Result<HttpClient> {
+        let mut client = reqwest::Client::builder();
+        #[cfg(feature = "tls-pinning")]
+        if let Some(tls) = &self.tls {
+            client = tls.apply(client)?;
+        }
+        if let Some(user_agent) = self.user_agent {
+            client = client.user_agent(user_agent);
+        }
+        if self.http1_only {
+            client = client.http1_only();
+        }
+        if let Some(timeout) = self.connect_timeout {
+            client = client.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            client = client.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            client = client.pool_max_idle_per_host(max);
+        }
+        let mut http = HttpClient::new(client.build()?, self.allowed_domains);
+        http.read_timeout = self.read_timeout;
+        http.retry = self.retry;
+        #[cfg(feature = "tls-pinning")]
+        if let Some(tls) = &self.tls {
+            http.pinned_domains = tls.pinned_domains();
+        }
+        Ok(http)
+    }
 }
 
 impl HttpClient {
@@ -69,38 +417,698 @@ impl HttpClient {
         Self {
             client,
             allowed_domains,
+            budget: None,
+            validators: Mutex::new(HashMap::new()),
+            proxy_clients: Mutex::new(HashMap::new()),
+            warmed_up: Mutex::new(HashMap::new()),
+            charset: None,
+            retry: None,
+            read_timeout: None,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            min_body_len: None,
+            success_statuses: (200..300).collect(),
+            rewriter: None,
+            #[cfg(feature = "tls-pinning")]
+            pinned_domains: HashSet::new(),
         }
     }
+
+    pub fn builder(allowed_domains: HashSet<String>) -> HttpClientBuilder {
+        HttpClientBuilder {
+            allowed_domains,
+            ..Default::default()
+        }
+    }
+
+    pub fn allowed_domains(&self) -> &HashSet<String> {
+        &self.allowed_domains
+    }
+
+    /// Forget the validators kept for conditional requests and drop the clients of the
+    /// proxies.
+    pub fn clear_cache(&self) {
+        self.validators
+            .lock()
+            .expect("validators lock poisoned")
+            .clear();
+        self.proxy_clients
+            .lock()
+            .expect("proxy clients lock poisoned")
+            .clear();
+    }
+
+    /// Release the client along with its cached state and the clients of the proxies.
+    ///
+    /// reqwest keeps idle connections in a pool shared by the clones of a client, so they
+    /// are only closed once every clone is dropped; bound the pool with
+    /// [`HttpClientBuilder::pool_idle_timeout`] and
+    /// [`HttpClientBuilder::pool_max_idle_per_host`] for long-lived clients.
+    pub fn shutdown(self) {
+        self.clear_cache();
+        drop(self);
+    }
+
+    /// Allow an extra domain beyond the ones the schema declares, e.g. a shared image cdn.
+    pub fn allow_domain(&mut self, domain: impl Into<String>) {
+        self.allowed_domains.insert(domain.into());
+    }
+
+    pub fn extend_allowed<I>(&mut self, domains: I)
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.allowed_domains.extend(domains.into_iter().map(Into::into));
+    }
+
+    /// Fail with [`SchemaError::BodyTooLarge`] on a body longer than `max` bytes, instead
+    /// of the default [`DEFAULT_MAX_BODY_BYTES`].
+    pub fn with_max_body_bytes(mut self, max: usize) -> Self {
+        self.max_body_bytes = max;
+        self
+    }
+
+    /// Decode text with this encoding when a response does not declare its charset.
+    pub fn with_charset(mut self, charset: &'static encoding_rs::Encoding) -> Self {
+        self.charset = Some(charset);
+        self
+    }
+
+    /// Retry failed requests that are [idempotent](HttpRequest::is_idempotent).
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Treat a text body shorter than `min_len` bytes, ignoring surrounding whitespace, as
+    /// a failure of flaky servers answering with an empty page.
+    ///
+    /// Such bodies are retried under the [retry policy](HttpClient::with_retry), and fail
+    /// with [`SchemaError::EmptyResponse`] once the retries are used up. Only applies to
+    /// [`HttpClient::request`]; a `min_len` of 1 catches blank bodies.
+    pub fn with_retry_on_empty_body(mut self, min_len: usize) -> Self {
+        self.min_body_len = Some(min_len);
+        self
+    }
+
+    /// Accept responses of these statuses besides the 2xx ones, e.g. `[404]` for a site
+    /// whose error pages are parsed by the schema.
+    ///
+    /// Responses of any other status fail with [`SchemaError::UnexpectedStatus`] once the
+    /// retries are used up; a `304 Not Modified` answering
+    /// [`HttpClient::request_conditional`] is always accepted.
+    pub fn with_success_statuses(mut self, statuses: impl IntoIterator<Item = u16>) -> Self {
+        self.success_statuses.extend(statuses);
+        self
+    }
+
+    /// Rewrite the url of every request before it is checked and sent.
+    pub fn with_url_rewriter(mut self, rewriter: UrlRewriter) -> Self {
+        self.rewriter = Some(rewriter);
+        self
+    }
+
+    /// Share a download budget with this client; requests fail once it is used up.
+    pub fn with_budget(mut self, budget: ByteBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
     pub async fn request(&self, request: HttpRequest) -> Result<String> {
-        let url = reqwest::Url::parse(&request.url)
-            .map_err(|e| SchemaError::InvalidUrl(format!("{} for {}", e, request.url)))?;
-        if let Some(domain) = url.domain() {
-            if !self.allowed_domains.contains(domain) {
-                Err(SchemaError::NotAllowedDomain(domain.to_string()))?
-            } else {
-                let mut builder = self.client.request(request.method.into_inner(), url);
-                for (key, value) in request.headers.into_iter() {
-                    builder = builder.header(key, value);
+        self.request_page(request).await.map(|(body, _)| body)
+    }
+
+    /// Request the page like [`HttpClient::request`], along with the url it ended up at
+    /// after following redirects.
+    pub async fn request_page(&self, request: HttpRequest) -> Result<(String, String)> {
+        let Some(min_len) = self.min_body_len else {
+            let response = self.send(request).await?;
+            let final_url = response.url().to_string();
+            return Ok((self.text(response).await?, final_url));
+        };
+        let retry = self.retry.as_ref().filter(|_| request.is_idempotent());
+        let mut attempt = 0;
+        loop {
+            let response = self.send(request.clone()).await?;
+            let final_url = response.url().to_string();
+            let body = self.text(response).await?;
+            if body.trim().len() >= min_len {
+                return Ok((body, final_url));
+            }
+            match retry {
+                Some(retry) if attempt < retry.max_retries => {
+                    attempt += 1;
+                    tokio::time::sleep(retry.delay).await;
                 }
-                if !request.body.is_empty() {
-                    builder = builder.body(request.body);
+                _ => Err(SchemaError::EmptyResponse)?,
+            }
+        }
+    }
+
+    /// Send this one request with another client, e.g. one that does not follow redirects,
+    /// still checking it against the legal domains.
+    pub async fn request_with(
+        &self,
+        request: HttpRequest,
+        client: &reqwest::Client,
+    ) -> Result<String> {
+        let response = self.send_with(client, request, false).await?;
+        self.text(response).await
+    }
+
+    /// Send a request of the host itself without checking its domain against the legal
+    /// domains, e.g. to follow a login redirect to an auth domain the schema does not know.
+    ///
+    /// This bypasses the protection of the allowlist: only use it for urls the host trusts,
+    /// never for urls coming from a schema.
+    pub async fn request_trusted(&self, request: HttpRequest) -> Result<String> {
+        let response = self.send_with(&self.client, request, true).await?;
+        self.text(response).await
+    }
+
+    /// Send the request through a proxy, e.g. `http://127.0.0.1:8080` or `socks5://...`.
+    ///
+    /// The client of each proxy is kept for later requests until it is idle for five
+    /// minutes or evicted as the least recently used of more than 16 proxies. It does not
+    /// share the cookies, timeouts or pool settings of the main client.
+    pub async fn request_via_proxy(&self, request: HttpRequest, proxy: &str) -> Result<String> {
+        let client = self.proxy_client(proxy)?;
+        let response = self.send_with(&client, request, false).await?;
+        self.text(response).await
+    }
+
+    /// The cached client of the proxy, evicting the stale ones.
+    fn proxy_client(&self, proxy: &str) -> Result<reqwest::Client> {
+        let mut clients = self.proxy_clients.lock().expect("proxy clients lock poisoned");
+        clients.retain(|_, client| client.last_used.elapsed() < PROXY_CLIENT_IDLE);
+        if let Some(cached) = clients.get_mut(proxy) {
+            cached.last_used = Instant::now();
+            return Ok(cached.client.clone());
+        }
+        if clients.len() >= MAX_PROXY_CLIENTS {
+            let oldest = clients
+                .iter()
+                .min_by_key(|(_, client)| client.last_used)
+                .map(|(proxy, _)| proxy.clone());
+            if let Some(oldest) = oldest {
+                clients.remove(&oldest);
+            }
+        }
+        let client = reqwest::Proxy::all(proxy)
+            .and_then(|proxy| reqwest::Client::builder().proxy(proxy).build())
+            .map_err(|e| SchemaError::InvalidRequest(format!("invalid proxy {}: {}", proxy, e)))?;
+        let cached = ProxyClient {
+            client: client.clone(),
+            last_used: Instant::now(),
+        };
+        clients.insert(proxy.to_string(), cached);
+        Ok(client)
+    }
+
+    /// Request the raw body without checking that it is text.
+    pub async fn request_bytes(&self, request: HttpRequest) -> Result<bytes::Bytes> {
+        self.request_bytes_with_progress(request, |_, _| {}).await
+    }
+
+    /// Request the raw body like [`HttpClient::request_bytes`], calling `progress` with
+    /// the bytes downloaded so far and the `Content-Length`, if any, as the chunks arrive.
+    pub async fn request_bytes_with_progress(
+        &self,
+        request: HttpRequest,
+        progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<bytes::Bytes> {
+        let response = self.send(request).await?;
+        let bytes = self.read_body_with_progress(response, progress).await?;
+        self.consume_budget(bytes.len())?;
+        Ok(bytes)
+    }
+
+    /// Request an image, returning its bytes and content type.
+    ///
+    /// Fails with [`SchemaError::UnexpectedContentType`] if the response is not an image.
+    pub async fn request_image(&self, request: HttpRequest) -> Result<(bytes::Bytes, String)> {
+        let response = self.send(request).await?;
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !content_type.to_ascii_lowercase().starts_with("image/") {
+            Err(SchemaError::UnexpectedContentType {
+                expected: "image",
+                got: content_type.clone(),
+            })?
+        }
+        let bytes = self.read_body(response).await?;
+        self.consume_budget(bytes.len())?;
+        Ok((bytes, content_type))
+    }
+
+    /// Request a page once per client, e.g. a homepage setting the cookies a site requires.
+    ///
+    /// The cookies are only kept if the reqwest client has a cookie store.
+    ///
+    /// Concurrent callers for the same url wait for the first request. A failed warm-up is
+    /// not retried, its error is only returned to the caller that made the request.
+    pub async fn warm_up(&self, url: &str) -> Result<()> {
+        let cell = self
+            .warmed_up
+            .lock()
+            .expect("warm-up lock poisoned")
+            .entry(url.to_string())
+            .or_default()
+            .clone();
+        let mut result = Ok(());
+        cell.get_or_init(|| async {
+            result = self
+                .send(HttpRequest {
+                    url: url.to_string(),
+                    ..Default::default()
+                })
+                .await
+                .map(drop);
+        })
+        .await;
+        result
+    }
+
+    /// Request the page, sending the validators of the last response from the same url.
+    ///
+    /// Returns `Ok(None)` when the server reports that the page is not modified.
+    pub async fn request_conditional(&self, mut request: HttpRequest) -> Result<Option<String>> {
+        let url = request.url.clone();
+        let validators = self
+            .validators
+            .lock()
+            .expect("validators lock poisoned")
+            .get(&url)
+            .cloned();
+        if let Some(validators) = validators {
+            if let Some(etag) = validators.etag {
+                request.headers.insert(reqwest::header::IF_NONE_MATCH.to_string(), etag);
+            }
+            if let Some(last_modified) = validators.last_modified {
+                let name = reqwest::header::IF_MODIFIED_SINCE.to_string();
+                request.headers.insert(name, last_modified);
+            }
+        }
+        let response = self.send(request).await?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let header = |name: reqwest::header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let validators = Validators {
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+            stored_at: Instant::now(),
+        };
+        {
+            let mut cache = self.validators.lock().expect("validators lock poisoned");
+            if cache.len() >= MAX_VALIDATORS && !cache.contains_key(&url) {
+                let oldest = cache
+                    .iter()
+                    .min_by_key(|(_, validators)| validators.stored_at)
+                    .map(|(url, _)| url.clone());
+                if let Some(oldest) = oldest {
+                    cache.remove(&oldest);
                 }
-                let response = builder.send().await?;
-                let text = response.text().await?;
-                Ok(text)
             }
-        } else {
-            Err(SchemaError::InvalidUrl(format!(
+            cache.insert(url, validators);
+        }
+        self.text(response).await.map(Some)
+    }
+
+    pub async fn request_full(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let response = self.send(request).await?;
+        let status = response.status().as_u16();
+        let status_text = response
+            .status()
+            .canonical_reason()
+            .unwrap_or_default()
+            .to_string();
+        let http_version = format!("{:?}", response.version());
+        let final_url = response.url().to_string();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (key.as_str().to_string(), value.to_string()))
+            })
+            .collect();
+        let (body, encoding) = self.decode(response).await?;
+        Ok(HttpResponse {
+            status,
+            status_text,
+            http_version,
+            headers,
+            final_url,
+            body,
+            encoding: encoding.name(),
+        })
+    }
+
+    /// Decode the body as text, refusing bodies that are clearly binary.
+    async fn text(&self, response: reqwest::Response) -> Result<String> {
+        self.decode(response).await.map(|(text, _)| text)
+    }
+
+    /// Decode the body with the charset declared by the response or the schema.
+    ///
+    /// Without a declared charset the body is decoded as utf-8, falling back to the
+    /// encoding of [`FALLBACK_ENCODINGS`] with the fewest replacement characters if that
+    /// fails.
+    async fn decode(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<(String, &'static encoding_rs::Encoding)> {
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if is_binary_content_type(&content_type) {
+            Err(SchemaError::UnexpectedContentType {
+                expected: "text",
+                got: content_type.clone(),
+            })?
+        }
+        let declared = content_type
+            .split(';')
+            .skip(1)
+            .find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("charset")
+                    .then(|| value.trim().trim_matches('"'))
+            })
+            .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()));
+        let bytes = self.read_body(response).await?;
+        self.consume_budget(bytes.len())?;
+        let (text, encoding) = decode_text(&bytes, declared.or(self.charset));
+        if text.chars().take(BINARY_SNIFF_LEN).any(|c| c == '\0') {
+            Err(SchemaError::UnexpectedContentType {
+                expected: "text",
+                got: content_type,
+            })?
+        }
+        Ok((text, encoding))
+    }
+
+    /// Read the body, failing as soon as it grows past the limit.
+    async fn read_body(&self, response: reqwest::Response) -> Result<bytes::Bytes> {
+        self.read_body_with_progress(response, |_, _| {}).await
+    }
+
+    async fn read_body_with_progress(
+        &self,
+        mut response: reqwest::Response,
+        mut progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<bytes::Bytes> {
+        let too_large = || SchemaError::BodyTooLarge(self.max_body_bytes);
+        // read before the body, as the hint shrinks while it is read
+        let total = response.content_length();
+        if total.is_some_and(|length| length > self.max_body_bytes as u64) {
+            Err(too_large())?
+        }
+        let mut body = bytes::BytesMut::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > self.max_body_bytes {
+                Err(too_large())?
+            }
+            body.extend_from_slice(&chunk);
+            progress(body.len() as u64, total);
+        }
+        Ok(body.freeze())
+    }
+
+    fn consume_budget(&self, bytes: usize) -> SchemaResult<()> {
+        match &self.budget {
+            Some(budget) => budget.consume(bytes as u64),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the url against the legal domains.
+    ///
+    /// A bare entry (`example.com`) allows the host on any scheme and port, while entries
+    /// like `example.com:8443` or `https://example.com` also require the given port or scheme.
+    fn is_allowed(&self, url: &reqwest::Url, domain: &str) -> bool {
+        self.allowed_domains.contains(domain)
+            || self
+                .allowed_domains
+                .iter()
+                .filter_map(|entry| LegalDomain::parse(entry))
+                .any(|legal| legal.matches(url, domain))
+    }
+
+    async fn send(&self, request: HttpRequest) -> Result<reqwest::Response> {
+        self.send_with(&self.client, request, false).await
+    }
+
+    /// Send the request, checking its domain unless it is `trusted`.
+    async fn send_with(
+        &self,
+        client: &reqwest::Client,
+        request: HttpRequest,
+        trusted: bool,
+    ) -> Result<reqwest::Response> {
+        let response = match &self.retry {
+            Some(retry) if retry.max_retries > 0 && request.is_idempotent() => {
+                let mut attempt = 0;
+                loop {
+                    let result = self.send_once(client, request.clone(), trusted).await;
+                    if !is_retryable(&result) || attempt >= retry.max_retries {
+                        break result?;
+                    }
+                    attempt += 1;
+                    tokio::time::sleep(retry.delay).await;
+                }
+            }
+            _ => self.send_once(client, request, trusted).await?,
+        };
+        let status = response.status();
+        let accepted = status == reqwest::StatusCode::NOT_MODIFIED
+            || self.success_statuses.contains(&status.as_u16());
+        if !accepted {
+            Err(SchemaError::UnexpectedStatus(status.as_u16()))?
+        }
+        Ok(response)
+    }
+
+    async fn send_once(
+        &self,
+        client: &reqwest::Client,
+        request: HttpRequest,
+        trusted: bool,
+    ) -> Result<reqwest::Response> {
+        if self.budget.as_ref().is_some_and(|budget| budget.remaining() == 0) {
+            Err(SchemaError::BudgetExceeded)?
+        }
+        let mut url = reqwest::Url::parse(&request.url)
+            .map_err(|e| SchemaError::InvalidUrl(format!("{} for {}", e, request.url)))?;
+        if let Some(rewriter) = &self.rewriter {
+            rewriter.rewrite(&mut url)?;
+        }
+        let host = request
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("host"))
+            .map(|(_, host)| host_name(host))
+            .transpose()?;
+        // a request to an ip address is checked against the host it asks for alone
+        let domains = match (url.domain(), host.as_deref()) {
+            (Some(domain), host) => [Some(domain), host],
+            (None, Some(host)) => [Some(host), None],
+            (None, None) => Err(SchemaError::InvalidUrl(format!(
                 "no domain in {}",
                 request.url
-            )))?
+            )))?,
+        };
+        if !trusted {
+            for domain in domains.into_iter().flatten() {
+                if !self.is_allowed(&url, domain) {
+                    Err(SchemaError::NotAllowedDomain(domain.to_string()))?
+                }
+            }
+        }
+        #[cfg(feature = "tls-pinning")]
+        let pinned = url
+            .domain()
+            .filter(|domain| self.pinned_domains.contains(*domain))
+            .map(|domain| domain.to_string());
+        let mut builder = client.request(request.method.into_inner(), url);
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.timeout(timeout);
+        }
+        let body = match request.json {
+            Some(json) => {
+                let has_content_type = request
+                    .headers
+                    .keys()
+                    .any(|key| key.eq_ignore_ascii_case("content-type"));
+                if !has_content_type {
+                    builder = builder.header(reqwest::header::CONTENT_TYPE, "application/json");
+                }
+                serde_json::to_vec(&json).map_err(|e| {
+                    SchemaError::InvalidRequest(format!("invalid json body: {}", e))
+                })?
+            }
+            None => request.body,
+        };
+        for (key, value) in request.headers.into_iter() {
+            builder = builder.header(key, value);
         }
+        if !body.is_empty() {
+            if request.compress_body {
+                builder = builder
+                    .header(reqwest::header::CONTENT_ENCODING, "gzip")
+                    .body(gzip(&body)?);
+            } else {
+                builder = builder.body(body);
+            }
+        }
+        let response = builder.send().await;
+        #[cfg(feature = "tls-pinning")]
+        if let (Err(e), Some(domain)) = (&response, pinned)
+            && tls::is_pin_mismatch(e)
+        {
+            Err(SchemaError::CertificateMismatch(domain))?
+        }
+        Ok(response?)
+    }
+}
+
+/// The domain or ip address of a `Host` header, without the port.
+fn host_name(host: &str) -> Result<String> {
+    reqwest::Url::parse(&format!("http://{}/", host.trim()))
+        .ok()
+        .filter(|url| {
+            url.username().is_empty()
+                && url.password().is_none()
+                && url.path() == "/"
+                && url.query().is_none()
+                && url.fragment().is_none()
+        })
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .ok_or_else(|| {
+            SchemaError::InvalidRequest(format!("invalid Host header: {:?}", host)).into()
+        })
+}
+
+/// Network errors, including timeouts, and server errors are worth retrying.
+fn is_retryable(result: &Result<reqwest::Response>) -> bool {
+    match result {
+        Ok(response) => response.status().is_server_error(),
+        Err(crate::Error::NetworkError(_)) => true,
+        Err(_) => false,
+    }
+}
+
+fn gzip(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+/// The encodings tried when a body without a declared charset is not valid utf-8.
+const FALLBACK_ENCODINGS: [&encoding_rs::Encoding; 3] =
+    [encoding_rs::GBK, encoding_rs::BIG5, encoding_rs::SHIFT_JIS];
+
+fn decode_text(
+    bytes: &[u8],
+    charset: Option<&'static encoding_rs::Encoding>,
+) -> (String, &'static encoding_rs::Encoding) {
+    if let Some(charset) = charset {
+        let (text, encoding, _) = charset.decode(bytes);
+        return (text.into_owned(), encoding);
+    }
+    let (text, encoding, had_errors) = encoding_rs::UTF_8.decode(bytes);
+    if !had_errors {
+        return (text.into_owned(), encoding);
+    }
+    std::iter::once((text, encoding))
+        .chain(FALLBACK_ENCODINGS.iter().map(|fallback| {
+            let (text, encoding, _) = fallback.decode(bytes);
+            (text, encoding)
+        }))
+        .min_by_key(|(text, _)| text.matches(char::REPLACEMENT_CHARACTER).count())
+        .map(|(text, encoding)| (text.into_owned(), encoding))
+        .expect("there is always the utf-8 decoding")
+}
+
+/// The longest body a client reads unless configured otherwise.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// The number of leading characters checked for NUL when sniffing binary bodies.
+const BINARY_SNIFF_LEN: usize = 1024;
+
+fn is_binary_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    ["image/", "audio/", "video/", "font/"]
+        .iter()
+        .any(|prefix| mime.starts_with(prefix))
+        || matches!(
+            mime.as_str(),
+            "application/octet-stream" | "application/pdf" | "application/zip" | "application/gzip"
+        )
+}
+
+pub(crate) struct LegalDomain<'a> {
+    scheme: Option<&'a str>,
+    pub(crate) host: &'a str,
+    port: Option<u16>,
+}
+
+impl<'a> LegalDomain<'a> {
+    pub(crate) fn parse(entry: &'a str) -> Option<Self> {
+        let (scheme, rest) = match entry.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme), rest),
+            None => (None, entry),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.parse().ok()?)),
+            None => (rest, None),
+        };
+        Some(Self { scheme, host, port })
+    }
+
+    fn matches(&self, url: &reqwest::Url, domain: &str) -> bool {
+        self.host.eq_ignore_ascii_case(domain)
+            && self
+                .scheme
+                .is_none_or(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+            && self
+                .port
+                .is_none_or(|port| url.port_or_known_default() == Some(port))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Error;
+    use std::io::Read;
+
+    use crate::{Error, hashset, tests::mock_url};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path},
+    };
 
     use super::*;
 
@@ -111,6 +1119,31 @@ mod tests {
         assert_eq!(method.into_inner(), reqwest::Method::GET);
     }
 
+    #[test]
+    fn test_legal_domains() {
+        let is_allowed = |allowed: &str, url: &str| {
+            let client = HttpClient::new(reqwest::Client::new(), hashset![allowed.to_string()]);
+            let url = reqwest::Url::parse(url).unwrap();
+            client.is_allowed(&url, url.domain().unwrap())
+        };
+        assert!(is_allowed("example.com", "http://example.com"));
+        assert!(is_allowed("example.com", "https://example.com:8443/book"));
+        assert!(!is_allowed("example.com", "https://www.example.com"));
+
+        assert!(is_allowed("example.com:8443", "https://example.com:8443/book"));
+        assert!(is_allowed("example.com:8443", "http://example.com:8443"));
+        assert!(!is_allowed("example.com:8443", "https://example.com"));
+
+        assert!(is_allowed("https://example.com", "https://example.com/book"));
+        assert!(!is_allowed("https://example.com", "http://example.com"));
+        assert!(is_allowed(
+            "https://example.com:8443",
+            "https://example.com:8443/book"
+        ));
+        assert!(!is_allowed("https://example.com:8443", "https://example.com"));
+        assert!(!is_allowed("https://example.com:8443", "http://example.com:8443"));
+    }
+
     #[tokio::test]
     async fn test_http_request() {
         let request = HttpRequest {
@@ -118,13 +1151,11 @@ mod tests {
             method: Method::from_bytes(b"GET").unwrap(),
             headers: HashMap::new(),
             body: Vec::new(),
+            ..Default::default()
         };
         let mut allowed_domains = HashSet::new();
         allowed_domains.insert("bilibili.com".to_string());
-        let client = HttpClient {
-            client: reqwest::Client::new(),
-            allowed_domains,
-        };
+        let client = HttpClient::new(reqwest::Client::new(), allowed_domains);
         let text = client.request(request).await.unwrap();
         assert!(text.contains("bilibili"));
 
@@ -133,10 +1164,768 @@ mod tests {
             method: Method::from_bytes(b"GET").unwrap(),
             headers: HashMap::new(),
             body: Vec::new(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client.request(request).await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_full_final_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/b/1"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/book/slug-1"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/book/slug-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let request = HttpRequest {
+            url: mock_url(&server, "/b/1"),
+            ..Default::default()
+        };
+        let response = client.request_full(request).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.status_text, "OK");
+        assert_eq!(response.http_version, "HTTP/1.1");
+        assert_eq!(response.final_url, mock_url(&server, "/book/slug-1"));
+        assert_eq!(response.body, "book");
+    }
+
+    #[tokio::test]
+    async fn test_http1_only() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .mount(&server)
+            .await;
+        let builder = HttpClient::builder(hashset!["localhost".to_string()]).http1_only();
+        assert!(builder.http1_only);
+        let client = builder.build().unwrap();
+        let request = HttpRequest {
+            url: mock_url(&server, "/book"),
+            ..Default::default()
+        };
+        let response = client.request_full(request).await.unwrap();
+        assert_eq!(response.http_version, "HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn test_user_agent() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/default"))
+            .and(header("User-Agent", "MyApp/1.0 (+https://example.com)"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("default"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/override"))
+            .and(header("User-Agent", "custom"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("override"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = HttpClient::builder(hashset!["localhost".to_string()])
+            .user_agent("MyApp/1.0 (+https://example.com)")
+            .build()
+            .unwrap();
+        let request = HttpRequest {
+            url: mock_url(&server, "/default"),
+            ..Default::default()
+        };
+        assert_eq!(client.request(request).await.unwrap(), "default");
+        let request = HttpRequest {
+            url: mock_url(&server, "/override"),
+            headers: HashMap::from([("User-Agent".to_string(), "custom".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(client.request(request).await.unwrap(), "override");
+    }
+
+    #[tokio::test]
+    async fn test_url_rewriter() {
+        let rewriter = UrlRewriter::new().with_host("example.com", "mirror.example.com");
+        let mut url = reqwest::Url::parse("https://example.com/book?id=1").unwrap();
+        rewriter.rewrite(&mut url).unwrap();
+        assert_eq!(url.as_str(), "https://mirror.example.com/book?id=1");
+        let mut url = reqwest::Url::parse("https://www.example.com/book").unwrap();
+        rewriter.rewrite(&mut url).unwrap();
+        assert_eq!(url.as_str(), "https://www.example.com/book");
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let request = || HttpRequest {
+            url: format!("http://example.com:{}/book", server.address().port()),
+            ..Default::default()
+        };
+        let rewriter = UrlRewriter::new().with_host("example.com", "localhost");
+        // the original domain is not checked, the mirror is
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["example.com".to_string()])
+            .with_url_rewriter(rewriter.clone());
+        assert!(matches!(
+            client.request(request()).await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(domain))) if domain == "localhost"
+        ));
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_url_rewriter(rewriter);
+        assert_eq!(client.request(request()).await.unwrap(), "book");
+    }
+
+    #[tokio::test]
+    async fn test_host_header() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .and(header("Host", "localhost"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let request = |host: Option<&str>| HttpRequest {
+            url: format!("http://127.0.0.1:{}/book", server.address().port()),
+            headers: host
+                .map(|host| HashMap::from([("Host".to_string(), host.to_string())]))
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        assert_eq!(client.request(request(Some("localhost"))).await.unwrap(), "book");
+        assert!(matches!(
+            client.request(request(Some("evil.com:8080"))).await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(domain))) if domain == "evil.com"
+        ));
+        assert!(matches!(
+            client.request(request(Some("127.0.0.1"))).await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(domain))) if domain == "127.0.0.1"
+        ));
+        for host in ["evil.com:abc", "localhost/book", "user@localhost"] {
+            assert!(matches!(
+                client.request(request(Some(host))).await,
+                Err(Error::SchemaError(SchemaError::InvalidRequest(_)))
+            ));
+        }
+        assert!(matches!(
+            client.request(request(None)).await,
+            Err(Error::SchemaError(SchemaError::InvalidUrl(_)))
+        ));
+        // an allowed host does not vouch for the domain the request is sent to
+        let request = HttpRequest {
+            url: format!("http://evil.com:{}/book", server.address().port()),
+            headers: HashMap::from([("Host".to_string(), "localhost".to_string())]),
+            ..Default::default()
         };
         assert!(matches!(
             client.request(request).await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(domain))) if domain == "evil.com"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_binary_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec(),
+                "image/png",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/chapter"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(b"text\0text".to_vec(), "text/html"),
+            )
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let request = |path: &str| HttpRequest {
+            url: mock_url(&server, path),
+            ..Default::default()
+        };
+        let result = client.request(request("/cover.png")).await;
+        assert!(matches!(
+            result,
+            Err(Error::SchemaError(SchemaError::UnexpectedContentType { got, .. }))
+                if got == "image/png"
+        ));
+        assert!(matches!(
+            client.request(request("/chapter")).await,
+            Err(Error::SchemaError(SchemaError::UnexpectedContentType { .. }))
+        ));
+        let bytes = client.request_bytes(request("/cover.png")).await.unwrap();
+        assert!(bytes.starts_with(b"\x89PNG"));
+    }
+
+    #[tokio::test]
+    async fn test_request_bytes_with_progress() {
+        let body = vec![7u8; 256 * 1024];
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body.clone(), "image/png"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let request = HttpRequest {
+            url: mock_url(&server, "/cover.png"),
+            ..Default::default()
+        };
+        let mut calls = Vec::new();
+        let bytes = client
+            .request_bytes_with_progress(request, |downloaded, total| {
+                calls.push((downloaded, total))
+            })
+            .await
+            .unwrap();
+        assert_eq!(bytes.len(), body.len());
+        assert!(!calls.is_empty());
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(calls.last(), Some(&(body.len() as u64, Some(body.len() as u64))));
+    }
+
+    #[tokio::test]
+    async fn test_byte_budget() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/chapter"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("0123456789"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let budget = ByteBudget::new(20);
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_budget(budget.clone());
+        let request = || HttpRequest {
+            url: mock_url(&server, "/chapter"),
+            ..Default::default()
+        };
+        client.request(request()).await.unwrap();
+        client.request(request()).await.unwrap();
+        assert_eq!(budget.remaining(), 0);
+        assert!(matches!(
+            client.request(request()).await,
+            Err(Error::SchemaError(SchemaError::BudgetExceeded))
+        ));
+
+        // the downloaded bytes count, not the length of the decoded text
+        let (gbk, _, _) = encoding_rs::GBK.encode("你好");
+        Mock::given(method("GET"))
+            .and(path("/gbk"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(gbk.to_vec(), "text/html; charset=gbk"),
+            )
+            .mount(&server)
+            .await;
+        let budget = ByteBudget::new(10);
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_budget(budget.clone());
+        let request = HttpRequest {
+            url: mock_url(&server, "/gbk"),
+            ..Default::default()
+        };
+        assert_eq!(client.request(request).await.unwrap(), "你好");
+        assert_eq!(budget.remaining(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_compress_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api"))
+            .respond_with(|request: &wiremock::Request| {
+                let body = match request.headers.get("Content-Encoding") {
+                    Some(encoding) if encoding == "gzip" => {
+                        let mut body = String::new();
+                        flate2::read::GzDecoder::new(&request.body[..])
+                            .read_to_string(&mut body)
+                            .unwrap();
+                        body
+                    }
+                    _ => String::from_utf8(request.body.clone()).unwrap(),
+                };
+                ResponseTemplate::new(200).set_body_string(body)
+            })
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let request = |body: &[u8]| HttpRequest {
+            url: mock_url(&server, "/api"),
+            method: Method::from_bytes(b"POST").unwrap(),
+            headers: HashMap::from([("Accept".to_string(), "application/json".to_string())]),
+            body: body.to_vec(),
+            compress_body: true,
+            ..Default::default()
+        };
+        let text = client.request(request(br#"{"q":"keyword"}"#)).await.unwrap();
+        assert_eq!(text, r#"{"q":"keyword"}"#);
+
+        let text = client.request(request(b"")).await.unwrap();
+        assert_eq!(text, "");
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests[0].headers.get("Accept").unwrap(), "application/json");
+        assert!(requests[1].headers.get("Content-Encoding").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_conditional() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_string("book"),
+            )
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let request = || HttpRequest {
+            url: mock_url(&server, "/book"),
+            ..Default::default()
+        };
+        let text = client.request_conditional(request()).await.unwrap();
+        assert_eq!(text.as_deref(), Some("book"));
+        let text = client.request_conditional(request()).await.unwrap();
+        assert_eq!(text, None);
+    }
+
+    #[tokio::test]
+    async fn test_max_body_bytes() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/small"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("0123456789"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/large"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("0123456789a"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_max_body_bytes(10);
+        let request = |path: &str| HttpRequest {
+            url: mock_url(&server, path),
+            ..Default::default()
+        };
+        assert_eq!(client.request(request("/small")).await.unwrap(), "0123456789");
+        assert!(matches!(
+            client.request(request("/large")).await,
+            Err(Error::SchemaError(SchemaError::BodyTooLarge(10)))
+        ));
+        assert!(matches!(
+            client.request_bytes(request("/large")).await,
+            Err(Error::SchemaError(SchemaError::BodyTooLarge(10)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_string("book"),
+            )
+            .mount(&server)
+            .await;
+        let client = HttpClient::builder(hashset!["localhost".to_string()])
+            .pool_idle_timeout(Duration::from_secs(10))
+            .pool_max_idle_per_host(1)
+            .build()
+            .unwrap();
+        let request = HttpRequest {
+            url: mock_url(&server, "/book"),
+            ..Default::default()
+        };
+        client.request_conditional(request).await.unwrap();
+        assert_eq!(client.validators.lock().unwrap().len(), 1);
+        client.clear_cache();
+        assert!(client.validators.lock().unwrap().is_empty());
+
+        // the mock server acts as the proxy of the site
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["example.com".to_string()]);
+        let request = || HttpRequest {
+            url: "http://example.com/book".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(client.request_via_proxy(request(), &server.uri()).await.unwrap(), "book");
+        assert_eq!(client.proxy_clients.lock().unwrap().len(), 1);
+        for port in 0..MAX_PROXY_CLIENTS {
+            client.proxy_client(&format!("http://127.0.0.1:{}", port + 1)).unwrap();
+        }
+        let clients = client.proxy_clients.lock().unwrap();
+        assert_eq!(clients.len(), MAX_PROXY_CLIENTS);
+        // the least recently used proxy is evicted first
+        assert!(!clients.contains_key(&server.uri()));
+        drop(clients);
+        if let Some(stale) = Instant::now().checked_sub(PROXY_CLIENT_IDLE) {
+            for cached in client.proxy_clients.lock().unwrap().values_mut() {
+                cached.last_used = stale;
+            }
+            client.proxy_client("http://127.0.0.1:1").unwrap();
+            assert_eq!(client.proxy_clients.lock().unwrap().len(), 1);
+        }
+        client.clear_cache();
+        assert!(client.proxy_clients.lock().unwrap().is_empty());
+        client.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_request_image() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(b"\x89PNG\r\n\x1a\n".to_vec(), "image/png"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/cover.html"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("<html>", "text/html"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let request = |path: &str| HttpRequest {
+            url: mock_url(&server, path),
+            ..Default::default()
+        };
+        let (bytes, content_type) = client.request_image(request("/cover.png")).await.unwrap();
+        assert_eq!(&bytes[..], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(content_type, "image/png");
+        assert!(matches!(
+            client.request_image(request("/cover.html")).await,
+            Err(Error::SchemaError(SchemaError::UnexpectedContentType { expected: "image", .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_allow_domain() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cdn"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("cdn"))
+            .mount(&server)
+            .await;
+        let mut client =
+            HttpClient::new(reqwest::Client::new(), hashset!["example.com".to_string()]);
+        let request = || HttpRequest {
+            url: mock_url(&server, "/cdn"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client.request(request()).await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(_)))
+        ));
+        client.allow_domain("localhost");
+        assert_eq!(client.request(request()).await.unwrap(), "cdn");
+        client.extend_allowed(["cdn.example.com", "img.example.com"]);
+        assert_eq!(
+            client.allowed_domains(),
+            &hashset![
+                "example.com".to_string(),
+                "localhost".to_string(),
+                "cdn.example.com".to_string(),
+                "img.example.com".to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_retry(RetryPolicy::new(2, Duration::from_millis(10)));
+        let request = |path: &str, idempotent: Option<bool>| HttpRequest {
+            url: mock_url(&server, path),
+            method: Method::from_bytes(b"POST").unwrap(),
+            idempotent,
+            ..Default::default()
+        };
+        for (path, idempotent) in [("/unmarked", None), ("/marked", Some(true))] {
+            assert!(matches!(
+                client.request(request(path, idempotent)).await,
+                Err(Error::SchemaError(SchemaError::UnexpectedStatus(503)))
+            ));
+        }
+        let requests = server.received_requests().await.unwrap();
+        let count = |p: &str| requests.iter().filter(|r| r.url.path() == p).count();
+        assert_eq!(count("/unmarked"), 1);
+        assert_eq!(count("/marked"), 3);
+        assert!(!request("/", None).is_idempotent());
+        assert!(HttpRequest::default().is_idempotent());
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_empty_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/chapter"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(" \n"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/chapter"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("chapter"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/empty"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_retry(RetryPolicy::new(2, Duration::from_millis(10)))
+            .with_retry_on_empty_body(1);
+        let request = |path: &str| HttpRequest {
+            url: mock_url(&server, path),
+            ..Default::default()
+        };
+        assert_eq!(client.request(request("/chapter")).await.unwrap(), "chapter");
+        assert!(matches!(
+            client.request(request("/empty")).await,
+            Err(Error::SchemaError(SchemaError::EmptyResponse))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_success_statuses() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(206).set_body_raw(b"\x89PNG".to_vec(), "image/png"))
+            .mount(&server)
+            .await;
+        let request = || HttpRequest {
+            url: mock_url(&server, "/cover.png"),
+            ..Default::default()
+        };
+        Mock::given(method("GET"))
+            .and(path("/missing"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("not found"))
+            .mount(&server)
+            .await;
+        let missing = || HttpRequest {
+            url: mock_url(&server, "/missing"),
+            ..Default::default()
+        };
+        let client = || HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        assert_eq!(&client().request_bytes(request()).await.unwrap()[..], b"\x89PNG");
+        assert!(matches!(
+            client().request(missing()).await,
+            Err(Error::SchemaError(SchemaError::UnexpectedStatus(404)))
+        ));
+        let lenient = client().with_success_statuses([404]);
+        assert_eq!(lenient.request(missing()).await.unwrap(), "not found");
+        assert!(lenient.request_bytes(request()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_request_with() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/special"))
+            .and(header("X-Client", "alternate"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("alternate"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let headers = reqwest::header::HeaderMap::from_iter([(
+            reqwest::header::HeaderName::from_static("x-client"),
+            reqwest::header::HeaderValue::from_static("alternate"),
+        )]);
+        let alternate = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        let request = |url: String| HttpRequest {
+            url,
+            ..Default::default()
+        };
+        let text = client
+            .request_with(request(mock_url(&server, "/special")), &alternate)
+            .await
+            .unwrap();
+        assert_eq!(text, "alternate");
+        assert!(matches!(
+            client
+                .request_with(request("https://example.com".to_string()), &alternate)
+                .await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_trusted() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("login"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["example.com".to_string()]);
+        let request = || HttpRequest {
+            url: mock_url(&server, "/login"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client.request(request()).await,
+            Err(Error::SchemaError(SchemaError::NotAllowedDomain(_)))
+        ));
+        assert_eq!(client.request_trusted(request()).await.unwrap(), "login");
+    }
+
+    #[tokio::test]
+    async fn test_charset_fallback() {
+        let server = MockServer::start().await;
+        let (gbk, _, _) = encoding_rs::GBK.encode("你好，世界");
+        Mock::given(method("GET"))
+            .and(path("/gbk"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(gbk.to_vec(), "text/html"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/utf8"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                "你好".as_bytes().to_vec(),
+                "text/html; charset=utf-8",
+            ))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let request = |path: &str| HttpRequest {
+            url: mock_url(&server, path),
+            ..Default::default()
+        };
+        let response = client.request_full(request("/gbk")).await.unwrap();
+        assert_eq!(response.body, "你好，世界");
+        assert_eq!(response.encoding, "GBK");
+        let response = client.request_full(request("/utf8")).await.unwrap();
+        assert_eq!(response.body, "你好");
+        assert_eq!(response.encoding, "UTF-8");
+    }
+
+    #[tokio::test]
+    async fn test_client_builder() {
+        let client = HttpClient::builder(hashset!["localhost".to_string()])
+            .connect_timeout(Duration::from_millis(200))
+            .read_timeout(Duration::from_millis(100))
+            .retry(RetryPolicy::new(1, Duration::from_millis(10)))
+            .build()
+            .unwrap();
+        assert_eq!(client.read_timeout, Some(Duration::from_millis(100)));
+        assert_eq!(client.retry.as_ref().unwrap().max_retries, 1);
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(300)))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let request = HttpRequest {
+            url: mock_url(&server, "/slow"),
+            ..Default::default()
+        };
+        let result = client.request(request).await;
+        assert!(matches!(result, Err(Error::NetworkError(e)) if e.is_timeout()));
+    }
+
+    #[cfg(feature = "tls-pinning")]
+    #[tokio::test]
+    async fn test_client_builder_tls() {
+        let fingerprint = "00".repeat(32);
+        let tls = TlsConfig::new().pin("example.com", &fingerprint).unwrap();
+        let client = HttpClient::builder(hashset!["localhost".to_string()])
+            .user_agent("LangHuan/1.0")
+            .tls(&tls)
+            .build()
+            .unwrap();
+        assert_eq!(client.pinned_domains, hashset!["example.com".to_string()]);
+
+        // the tls config does not drop the other settings of the builder
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .and(header("User-Agent", "LangHuan/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let request = HttpRequest {
+            url: mock_url(&server, "/book"),
+            ..Default::default()
+        };
+        assert_eq!(client.request(request).await.unwrap(), "book");
+    }
+
+    #[tokio::test]
+    async fn test_retry_classification() {
+        let client = reqwest::Client::new();
+        // nothing listens on port 1, so connecting fails
+        let refused = client.get("http://localhost:1").send().await.map_err(Error::from);
+        assert!(is_retryable(&refused));
+        let not_allowed: Result<reqwest::Response> =
+            Err(SchemaError::NotAllowedDomain("example.com".to_string()).into());
+        assert!(!is_retryable(&not_allowed));
+    }
+
+    #[tokio::test]
+    async fn test_warm_up() {
+        let server = MockServer::start().await;
+        for page in ["/", "/other"] {
+            Mock::given(method("GET"))
+                .and(path(page))
+                .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let client = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let (home, other) = (mock_url(&server, "/"), mock_url(&server, "/other"));
+        let (first, second, other) =
+            tokio::join!(client.warm_up(&home), client.warm_up(&home), client.warm_up(&other));
+        assert!(first.is_ok() && second.is_ok() && other.is_ok());
+        client.warm_up(&home).await.unwrap();
+
+        // a failed warm-up is not retried before the next request
+        let url = "https://www.example.com/";
+        assert!(matches!(
+            client.warm_up(url).await,
             Err(Error::SchemaError(SchemaError::NotAllowedDomain(_)))
         ));
+        client.warm_up(url).await.unwrap();
     }
 }
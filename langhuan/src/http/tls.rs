@@ -0,0 +1,214 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use rustls::{
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{SchemaError, SchemaResult};
+
+/// The message of the rustls error raised on a mismatch, used to recognize it in the
+/// error returned by reqwest.
+const MISMATCH_MESSAGE: &str = "certificate does not match the pinned fingerprint";
+
+/// Certificate pinning and extra root certificates for the clients of [`HttpClient`].
+///
+/// A pinned domain only accepts a server certificate whose sha256 fingerprint is pinned,
+/// other domains are verified against the web pki roots and the extra roots.
+///
+/// [`HttpClient`]: super::HttpClient
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    pins: HashMap<String, Vec<[u8; 32]>>,
+    roots: Vec<CertificateDer<'static>>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pin a certificate of the domain by its hex encoded sha256 fingerprint.
+    ///
+    /// Colons between the bytes, as printed by `openssl x509 -fingerprint`, are allowed.
+    pub fn pin(mut self, domain: impl Into<String>, fingerprint: &str) -> SchemaResult<Self> {
+        let hex: String = fingerprint.chars().filter(|c| *c != ':').collect();
+        let invalid =
+            || SchemaError::InvalidTlsConfig(format!("invalid fingerprint: {}", fingerprint));
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        self.pins.entry(domain.into()).or_default().push(bytes);
+        Ok(self)
+    }
+
+    /// Trust an extra root certificate in DER form, e.g. the one of a private ca.
+    pub fn with_root_certificate(mut self, der: Vec<u8>) -> Self {
+        self.roots.push(CertificateDer::from(der));
+        self
+    }
+
+    pub fn pinned_domains(&self) -> HashSet<String> {
+        self.pins.keys().cloned().collect()
+    }
+
+    /// A client builder verifying certificates with this config.
+    pub fn client_builder(&self) -> SchemaResult<reqwest::ClientBuilder> {
+        self.apply(reqwest::Client::builder())
+    }
+
+    /// Verify the certificates of the clients built by `builder` with this config, keeping
+    /// its other settings.
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> SchemaResult<reqwest::ClientBuilder> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        for root in &self.roots {
+            roots
+                .add(root.clone())
+                .map_err(|e| SchemaError::InvalidTlsConfig(e.to_string()))?;
+        }
+        let verifier = PinnedVerifier {
+            inner: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|e| SchemaError::InvalidTlsConfig(e.to_string()))?,
+            pins: self.pins.clone(),
+        };
+        let config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| SchemaError::InvalidTlsConfig(e.to_string()))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        Ok(builder.use_preconfigured_tls(config))
+    }
+}
+
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: HashMap<String, Vec<[u8; 32]>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let ServerName::DnsName(name) = server_name
+            && let Some(pins) = self.pins.get(name.as_ref())
+        {
+            let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+            return if pins.contains(&fingerprint) {
+                Ok(ServerCertVerified::assertion())
+            } else {
+                Err(rustls::Error::General(MISMATCH_MESSAGE.to_string()))
+            };
+        }
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Whether the request failed because a pinned certificate did not match.
+pub(super) fn is_pin_mismatch(error: &reqwest::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if let Some(rustls::Error::General(message)) = error.downcast_ref::<rustls::Error>()
+            && message == MISMATCH_MESSAGE
+        {
+            return true;
+        }
+        source = match error.downcast_ref::<std::io::Error>() {
+            Some(error) => error
+                .get_ref()
+                .map(|error| error as &(dyn std::error::Error + 'static)),
+            None => error.source(),
+        };
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_config() {
+        let certificate = CertificateDer::from(b"certificate".to_vec());
+        let fingerprint: String = Sha256::digest(certificate.as_ref())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let config = TlsConfig::new().pin("example.com", &fingerprint).unwrap();
+        assert_eq!(
+            config.pinned_domains(),
+            HashSet::from(["example.com".to_string()])
+        );
+        assert!(config.client_builder().unwrap().build().is_ok());
+        assert!(matches!(
+            TlsConfig::new().pin("example.com", "00:11"),
+            Err(SchemaError::InvalidTlsConfig(_))
+        ));
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let roots = Arc::new(RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        });
+        let verifier = PinnedVerifier {
+            inner: WebPkiServerVerifier::builder_with_provider(roots, provider)
+                .build()
+                .unwrap(),
+            pins: config.pins.clone(),
+        };
+        let verify = |certificate: &CertificateDer<'_>| {
+            let name = ServerName::try_from("example.com").unwrap();
+            verifier.verify_server_cert(certificate, &[], &name, &[], UnixTime::now())
+        };
+        assert!(verify(&certificate).is_ok());
+        let other = CertificateDer::from(b"other".to_vec());
+        assert!(matches!(
+            verify(&other),
+            Err(rustls::Error::General(message)) if message == MISMATCH_MESSAGE
+        ));
+    }
+}
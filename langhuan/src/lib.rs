@@ -1,7 +1,12 @@
 mod error;
 mod package;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+pub mod export;
 pub mod http;
+pub mod registry;
 pub mod runtime;
 pub mod schema;
 
@@ -22,4 +27,9 @@ pub(crate) mod tests {
                 }
             };
         }
+
+    /// build a url pointing at the mock server, using `localhost` so that it has a domain
+    pub(crate) fn mock_url(server: &wiremock::MockServer, path: &str) -> String {
+        format!("http://localhost:{}{}", server.address().port(), path)
+    }
 }
@@ -4,6 +4,8 @@ use mlua::{FromLua, UserData};
 
 #[cfg(feature = "pkg-json")]
 pub mod json;
+#[cfg(feature = "pkg-text")]
+pub mod text;
 #[cfg(feature = "pkg-url-encoding")]
 pub mod url;
 
@@ -0,0 +1,152 @@
+use mlua::{IntoLua, UserData};
+
+use super::Package;
+
+/// The tags that start a new line of the text.
+const BREAK_TAGS: [&str; 4] = ["br", "p", "div", "li"];
+
+/// The tags whose content is dropped along with the tags.
+const HIDDEN_TAGS: [&str; 2] = ["script", "style"];
+
+/// The longest entity decoded, `&#x10FFFF;` aside from the `&` and `;`.
+const MAX_ENTITY_LEN: usize = 8;
+
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "middot" => '·',
+        "copy" => '©',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// Decode the entities of the text, keeping the ones that are not known as they are.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= MAX_ENTITY_LEN)
+            .and_then(|end| Some((decode_entity(&rest[1..end + 1])?, end)));
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Convert a html fragment to plain text.
+///
+/// Tags are removed, entities decoded and whitespace collapsed, while `<br>`, `<p>` and
+/// the like start new lines. Blank lines are dropped.
+pub fn to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        text.push_str(&decode_entities(&rest[..start]).replace('\n', " "));
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        let name = tag_name(tag);
+        if BREAK_TAGS.contains(&name.as_str()) {
+            text.push('\n');
+        }
+        if HIDDEN_TAGS.contains(&name.as_str()) && !tag.starts_with('/') {
+            let close = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(index) => &rest[index..],
+                None => "",
+            };
+        }
+    }
+    text.push_str(&decode_entities(rest).replace('\n', " "));
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Default)]
+pub struct TextPackage;
+
+impl Package for TextPackage {
+    fn create_instance(&self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        Self.into_lua(lua)
+    }
+}
+
+impl UserData for TextPackage {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_function("to_text", |_, html: String| Ok(to_text(&html)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_text() {
+        let lua = mlua::Lua::new();
+        let package = TextPackage;
+        let instance = package.create_instance(&lua).unwrap();
+        lua.globals().set("text", instance).unwrap();
+        let result: String = lua
+            .load(
+                r#"
+                return text.to_text([[
+                    <p>Tom &amp; Jerry&nbsp;&nbsp;<b>chase</b>
+                    each other</p><p>&lt;again&gt; &#8220;&#x4E00;&#8221;<br/>&copy; 2024</p>
+                    <script>var a = "<p>";</script>
+                ]])
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(result, "Tom & Jerry chase each other\n<again> “一”\n© 2024");
+
+        assert_eq!(to_text("a < b && c &unknown;"), "a < b && c &unknown;");
+    }
+}
@@ -1,9 +1,43 @@
 use std::borrow::Cow;
 
 use mlua::{IntoLua, UserData};
+use percent_encoding::{AsciiSet, CONTROLS, NON_ALPHANUMERIC};
 
 use super::Package;
 
+/// The characters escaped by a url component, unreserved characters (RFC 3986) are kept.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The characters escaped in a query, as done by browsers.
+const QUERY: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'&')
+    .add(b'=')
+    .add(b'+');
+
+fn encoding(label: Option<&str>) -> mlua::Result<&'static encoding_rs::Encoding> {
+    let label = label.unwrap_or("utf-8");
+    encoding_rs::Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| mlua::Error::external(format!("invalid encoding:{}", label)))
+}
+
+fn encode_set(name: Option<&str>) -> mlua::Result<&'static AsciiSet> {
+    match name.unwrap_or("alphanumeric") {
+        "alphanumeric" => Ok(NON_ALPHANUMERIC),
+        "component" => Ok(COMPONENT),
+        "query" => Ok(QUERY),
+        name => Err(mlua::Error::external(format!("invalid encode set:{}", name))),
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct UrlPackage;
 
@@ -27,6 +61,34 @@ impl UserData for UrlPackage {
                     .to_string(),
             )
         });
+        // encode_query(params, { charset = "gbk", set = "component" }), with the keys sorted
+        methods.add_function(
+            "encode_query",
+            |_, (params, options): (mlua::Table, Option<mlua::Table>)| {
+                let (charset, set) = match &options {
+                    Some(options) => (
+                        options.get::<Option<String>>("charset")?,
+                        options.get::<Option<String>>("set")?,
+                    ),
+                    None => (None, None),
+                };
+                let encoding = encoding(charset.as_deref())?;
+                let set = encode_set(set.as_deref())?;
+                let mut pairs = params
+                    .pairs::<String, String>()
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                pairs.sort();
+                let encode = |text: &str| {
+                    let (encoded, _, _) = encoding.encode(text);
+                    percent_encoding::percent_encode(&encoded, set).to_string()
+                };
+                Ok(pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+                    .collect::<Vec<_>>()
+                    .join("&"))
+            },
+        );
         methods.add_function("decode", |_, (text, encoding): (String, Option<String>)| {
             let text: Cow<'_, [u8]> = percent_encoding::percent_decode_str(&text).into();
             let encoding_label = encoding.as_deref().unwrap_or("utf-8");
@@ -70,6 +132,35 @@ mod tests {
         assert_eq!(result, "Hello%20%C4%E3%BA%C3");
     }
 
+    #[test]
+    fn test_encode_query() {
+        let lua = mlua::Lua::new();
+        let package = UrlPackage;
+        let instance = package.create_instance(&lua).unwrap();
+        lua.globals().set("url", instance).unwrap();
+        let result: String = lua
+            .load(
+                r#"
+                return url.encode_query(
+                    {q = "你好 world", page = 2, sort = "a-b.c"},
+                    {charset = "gbk", set = "component"}
+                )
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(result, "page=2&q=%C4%E3%BA%C3%20world&sort=a-b.c");
+        let result: String = lua
+            .load(
+                r#"
+                return url.encode_query({q = "a-b"})
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(result, "q=a%2Db");
+    }
+
     #[test]
     fn test_decode() {
         let lua = mlua::Lua::new();
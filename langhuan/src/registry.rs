@@ -0,0 +1,346 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt, stream};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    Error, Result,
+    http::HttpClient,
+    schema::{Schema, SearchItem},
+};
+
+/// A collection of loaded schemas keyed by their id.
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    client: reqwest::Client,
+    schemas: HashMap<uuid::Uuid, Schema>,
+}
+
+impl SchemaRegistry {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            schemas: HashMap::new(),
+        }
+    }
+
+    /// Register a schema, returning the previous schema with the same id.
+    pub fn register(&mut self, schema: Schema) -> Option<Schema> {
+        self.schemas.insert(schema.schema_info.id, schema)
+    }
+
+    /// Register a schema, failing with [`Error::DuplicateId`] if its id is already taken.
+    pub fn try_register(&mut self, schema: Schema) -> Result<()> {
+        match self.schemas.entry(schema.schema_info.id) {
+            Entry::Occupied(entry) => Err(Error::DuplicateId(*entry.key())),
+            Entry::Vacant(entry) => {
+                entry.insert(schema);
+                Ok(())
+            }
+        }
+    }
+
+    /// Register a schema in place of any schema with the same id, which is returned.
+    pub fn register_or_replace(&mut self, schema: Schema) -> Option<Schema> {
+        self.register(schema)
+    }
+
+    /// Move the schemas of another registry into this one.
+    ///
+    /// Schemas whose id is already registered are not moved but returned, so that the
+    /// caller decides which one to keep.
+    pub fn merge(&mut self, other: SchemaRegistry) -> Vec<Schema> {
+        let mut conflicts = Vec::new();
+        for (id, schema) in other.schemas {
+            match self.schemas.entry(id) {
+                Entry::Occupied(_) => conflicts.push(schema),
+                Entry::Vacant(entry) => {
+                    entry.insert(schema);
+                }
+            }
+        }
+        conflicts
+    }
+
+    pub fn get(&self, id: &uuid::Uuid) -> Option<&Schema> {
+        self.schemas.get(id)
+    }
+
+    pub fn remove(&mut self, id: &uuid::Uuid) -> Option<Schema> {
+        self.schemas.remove(id)
+    }
+
+    /// Search the first page of every schema.
+    ///
+    /// At most `concurrency` schemas are queried at once, and a schema that takes longer
+    /// than `timeout` yields [`Error::Timeout`]. Results are yielded as they arrive, with
+    /// the [`SearchItem::source`] of every item set to the id of its schema.
+    ///
+    /// The searches run inside the stream rather than in spawned tasks, so cancelling
+    /// `cancel` or dropping the stream stops all outstanding requests. Once `cancel` is
+    /// cancelled, every search not finished yet yields [`Error::Cancelled`].
+    pub fn search_all<'a>(
+        &'a self,
+        keyword: &'a str,
+        concurrency: usize,
+        timeout: Duration,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = (uuid::Uuid, Result<Vec<SearchItem>>)> + 'a {
+        stream::iter(self.schemas.values())
+            .map(move |schema| {
+                let cancel = cancel.clone();
+                async move {
+                    let mut http = HttpClient::new(
+                        self.client.clone(),
+                        schema.schema_info.legal_domains.iter().cloned().collect(),
+                    );
+                    if let Some(encoding) = schema.schema_info.encoding() {
+                        http = http.with_charset(encoding);
+                    }
+                    if let Some(max) = schema.schema_info.max_body_bytes {
+                        http = http.with_max_body_bytes(max);
+                    }
+                    let search = Self::search_page(schema, keyword, &http);
+                    let result = tokio::select! {
+                        biased;
+                        _ = cancel.cancelled() => Err(Error::Cancelled),
+                        result = tokio::time::timeout(timeout, search) => {
+                            result.unwrap_or(Err(Error::Timeout))
+                        }
+                    };
+                    let result = result.map(|items: Vec<SearchItem>| {
+                        let source = schema.schema_info.id.to_string();
+                        items
+                            .into_iter()
+                            .map(|item| SearchItem {
+                                source: Some(source.clone()),
+                                ..item
+                            })
+                            .collect::<Vec<_>>()
+                    });
+                    (schema.schema_info.id, result)
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+    }
+
+    async fn search_page(
+        schema: &Schema,
+        keyword: &str,
+        http: &HttpClient,
+    ) -> Result<Vec<SearchItem>> {
+        let mut items = schema.search(keyword, http, None, None);
+        match items.next_page().await? {
+            Some(iter) => iter.collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    use super::*;
+    use crate::{runtime::Runtime, tests::mock_url};
+
+    const SCRIPT: &str = r#"--@id: {id}
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function search(keyword, page)
+    if page == 1 then
+        return "{url}"
+    end
+end
+local function search_parse(content)
+    local done = false
+    return function()
+        if not done then
+            done = true
+            return {
+                id = content,
+                title = "title",
+                author = "author",
+                cover = "cover",
+                last_update = "last_update",
+                status = "status",
+                intro = "intro",
+            }
+        end
+    end
+end
+local function test()
+end
+return {
+    search = {page = search, parse = search_parse},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#;
+
+    fn load(runtime: &Runtime, id: &str, url: &str) -> Schema {
+        let script = SCRIPT.replace("{id}", id).replace("{url}", url);
+        runtime.load(&script, "test").unwrap()
+    }
+
+    #[test]
+    fn test_try_register() {
+        let runtime = Runtime::new();
+        let id = "198ca153-ccae-4f82-9218-9b6657796b57";
+        let mut registry = SchemaRegistry::new(reqwest::Client::new());
+        registry
+            .try_register(load(&runtime, id, "https://www.example.com/first"))
+            .unwrap();
+        let second = || load(&runtime, id, "https://www.example.com/second");
+        let result = registry.try_register(second());
+        assert!(matches!(
+            result,
+            Err(Error::DuplicateId(duplicate)) if duplicate.to_string() == id
+        ));
+
+        let replaced = registry.register_or_replace(second());
+        assert!(replaced.unwrap().source().contains("/first"));
+        let current = registry.get(&uuid::Uuid::parse_str(id).unwrap()).unwrap();
+        assert!(current.source().contains("/second"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let runtime = Runtime::new();
+        let shared = "198ca153-ccae-4f82-9218-9b6657796b57";
+        let other_id = "2f1c4a9e-5b7d-4c3a-8e6f-0a1b2c3d4e5f";
+        let mut registry = SchemaRegistry::new(reqwest::Client::new());
+        registry.register(load(&runtime, shared, "https://www.example.com/ours"));
+        let mut other = SchemaRegistry::new(reqwest::Client::new());
+        other.register(load(&runtime, shared, "https://www.example.com/theirs"));
+        other.register(load(&runtime, other_id, "https://www.example.com/theirs"));
+
+        let conflicts = registry.merge(other);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].schema_info.id.to_string(), shared);
+        let kept = registry.get(&uuid::Uuid::parse_str(shared).unwrap()).unwrap();
+        assert!(kept.source().contains("/ours"));
+        assert!(registry.get(&uuid::Uuid::parse_str(other_id).unwrap()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_search_all() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/search"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("1")
+                    .set_delay(Duration::from_millis(200)),
+            )
+            .mount(&server)
+            .await;
+        let runtime = Runtime::new();
+        let mut registry = SchemaRegistry::new(reqwest::Client::new());
+        let ids = [
+            "198ca153-ccae-4f82-9218-9b6657796b57",
+            "2f1c4a9e-5b7d-4c3a-8e6f-0a1b2c3d4e5f",
+            "6a0f3c2e-1d4b-4e8a-9c7f-5b3a2d1e0f9c",
+            "9e8d7c6b-5a4f-4e3d-8c2b-1a0f9e8d7c6b",
+        ];
+        for id in &ids[..3] {
+            registry.register(load(&runtime, id, &mock_url(&server, "/search")));
+        }
+        registry.register(load(&runtime, ids[3], "https://www.example.com"));
+
+        let start = Instant::now();
+        let results: Vec<_> = registry
+            .search_all("keyword", 2, Duration::from_secs(10), CancellationToken::new())
+            .collect()
+            .await;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert_eq!(results.len(), 4);
+        for (id, result) in results {
+            if id == uuid::Uuid::parse_str(ids[3]).unwrap() {
+                assert!(matches!(
+                    result,
+                    Err(Error::SchemaError(crate::SchemaError::NotAllowedDomain(_)))
+                ));
+            } else {
+                let items = result.unwrap();
+                assert_eq!(items[0].id, "1");
+                assert_eq!(items[0].source, Some(id.to_string()));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_all_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/search"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("1")
+                    .set_delay(Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+        let runtime = Runtime::new();
+        let mut registry = SchemaRegistry::new(reqwest::Client::new());
+        registry.register(load(
+            &runtime,
+            "198ca153-ccae-4f82-9218-9b6657796b57",
+            &mock_url(&server, "/search"),
+        ));
+        let results: Vec<_> = registry
+            .search_all("keyword", 2, Duration::from_millis(100), CancellationToken::new())
+            .collect()
+            .await;
+        assert!(matches!(results[0].1, Err(Error::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_search_all_cancel() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/search"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("1")
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .mount(&server)
+            .await;
+        let runtime = Runtime::new();
+        let mut registry = SchemaRegistry::new(reqwest::Client::new());
+        for id in [
+            "198ca153-ccae-4f82-9218-9b6657796b57",
+            "2f1c4a9e-5b7d-4c3a-8e6f-0a1b2c3d4e5f",
+            "6a0f3c2e-1d4b-4e8a-9c7f-5b3a2d1e0f9c",
+        ] {
+            registry.register(load(&runtime, id, &mock_url(&server, "/search")));
+        }
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+        let results: Vec<_> = registry
+            .search_all("keyword", 1, Duration::from_secs(10), cancel)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|(_, result)| matches!(result, Err(Error::Cancelled))));
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
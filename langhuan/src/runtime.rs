@@ -2,11 +2,14 @@ use tracing::instrument;
 
 use crate::{
     package::{self, Package},
-    schema::Schema,
+    schema::{Schema, SchemaInfo, SchemaInfoSpec},
 };
 use std::{
     collections::HashMap,
-    sync::{Arc, LazyLock},
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
 };
 
 static RUNTIME_PACKAGES: LazyLock<HashMap<&'static str, Box<dyn Package + Send + Sync>>> =
@@ -19,12 +22,62 @@ static RUNTIME_PACKAGES: LazyLock<HashMap<&'static str, Box<dyn Package + Send +
         );
         #[cfg(feature = "pkg-url-encoding")]
         packages.insert("url", Box::new(package::url::UrlPackage));
+        #[cfg(feature = "pkg-text")]
+        packages.insert("text", Box::new(package::text::TextPackage));
         packages
     });
 
+/// What loading several files produced, each result paired with the file it came from.
+pub type LoadResults<K, T> = Vec<(K, Result<T, crate::Error>)>;
+
+/// The name of the module a runtime prelude is exposed as.
+const PRELUDE_MODULE: &str = "@prelude";
+/// The name of the module describing the runtime itself.
+const META_MODULE: &str = "@meta";
+
+/// Values such as api keys that a host provides to a schema by name, exposed to it as the
+/// read-only `secrets` table.
+///
+/// The `Debug` output lists only the names, so the values never end up in logs.
+#[derive(Clone, Default)]
+pub struct Secrets(HashMap<String, String>);
+
+impl From<HashMap<String, String>> for Secrets {
+    fn from(secrets: HashMap<String, String>) -> Self {
+        Self(secrets)
+    }
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.keys().map(|name| (name, "<redacted>")))
+            .finish()
+    }
+}
+
+/// A schema script compiled to Luau bytecode by [`Runtime::compile`].
+#[derive(Debug, Clone)]
+pub struct CompiledChunk {
+    source: Arc<str>,
+    bytecode: Arc<[u8]>,
+    fingerprint: String,
+}
+
+impl CompiledChunk {
+    /// The [fingerprint](Schema::fingerprint) of the source it was compiled from.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Runtime {
     lua: Arc<mlua::Lua>,
+    prelude: Option<Arc<str>>,
+    info_spec: SchemaInfoSpec,
+    /// compiled chunks by the fingerprint of their source
+    compiled: Arc<Mutex<HashMap<String, CompiledChunk>>>,
 }
 
 impl Default for Runtime {
@@ -37,34 +90,242 @@ impl Runtime {
     pub fn new() -> Self {
         let lua = mlua::Lua::new();
         lua.sandbox(true).expect("enable sandbox failed");
-        Self { lua: Arc::new(lua) }
+        Self {
+            lua: Arc::new(lua),
+            prelude: None,
+            info_spec: SchemaInfoSpec::default(),
+            compiled: Default::default(),
+        }
+    }
+
+    /// Create a runtime without the Luau sandbox, giving schemas the full standard library
+    /// and writable globals.
+    ///
+    /// Only use it for first-party schemas: an untrusted script loaded here can tamper with
+    /// the shared global state of every other schema in the runtime.
+    pub fn trusted() -> Self {
+        Self {
+            lua: Arc::new(mlua::Lua::new()),
+            prelude: None,
+            info_spec: SchemaInfoSpec::default(),
+            compiled: Default::default(),
+        }
+    }
+
+    /// Provide a trusted helper library that schemas can load with `require('@prelude')`.
+    ///
+    /// The prelude is evaluated lazily in the same sandboxed environment as the schemas.
+    pub fn with_prelude(mut self, lua_code: &str) -> Self {
+        self.prelude = Some(Arc::from(lua_code));
+        self
+    }
+
+    /// Require the metadata fields of the spec from the loaded schemas, instead of all of
+    /// them.
+    pub fn with_info_spec(mut self, spec: SchemaInfoSpec) -> Self {
+        self.info_spec = spec;
+        self
+    }
+
+    /// The names of the `@`-packages compiled into this build, without the `@` prefix.
+    pub fn available_packages() -> Vec<&'static str> {
+        let mut packages: Vec<_> = RUNTIME_PACKAGES.keys().copied().collect();
+        packages.sort_unstable();
+        packages
     }
 
     pub fn load(&self, code: &str, name: &str) -> Result<Schema, crate::Error> {
+        self.load_with_secrets(code, name, &Secrets::default())
+    }
+
+    /// Load a schema that can read the secrets from its `secrets` table.
+    ///
+    /// The secrets are only visible to this schema, not to the prelude or other schemas.
+    #[instrument(skip(self, code))]
+    pub fn load_with_secrets(
+        &self,
+        code: &str,
+        name: &str,
+        secrets: &Secrets,
+    ) -> Result<Schema, crate::Error> {
         let chunk = self
             .lua
             .load(code)
             .set_name(format!("={}", name))
-            .set_environment(self.create_environment()?);
-        let result = chunk.eval()?;
-        Schema::load(code, result)
+            .set_environment(self.create_environment(secrets)?);
+        let result = chunk.eval().map_err(crate::Error::LuaLoadError)?;
+        let mut schema = Schema::load_with_spec(&self.lua, code, result, &self.info_spec)?;
+        schema.secrets = secrets.clone();
+        Ok(schema)
+    }
+
+    /// Compile a schema script to bytecode, to load it later with
+    /// [`Runtime::load_compiled`] without parsing the Lua again.
+    ///
+    /// The chunks are cached by fingerprint, so compiling the same script twice is cheap.
+    pub fn compile(&self, code: &str) -> Result<CompiledChunk, crate::Error> {
+        let fingerprint = crate::schema::fingerprint(code);
+        let mut compiled = self.compiled.lock().expect("compiled chunks lock poisoned");
+        if let Some(chunk) = compiled.get(&fingerprint) {
+            return Ok(chunk.clone());
+        }
+        let bytecode = mlua::Compiler::new()
+            .compile(code)
+            .map_err(crate::Error::LuaLoadError)?;
+        let chunk = CompiledChunk {
+            source: Arc::from(code),
+            bytecode: Arc::from(bytecode),
+            fingerprint: fingerprint.clone(),
+        };
+        compiled.insert(fingerprint, chunk.clone());
+        Ok(chunk)
     }
 
-    fn create_environment(&self) -> mlua::Result<mlua::Table> {
-        let env = self.lua.create_table()?;
-        let globals = self.lua.globals();
-        env.set_metatable(globals.metatable());
-        let lua = self.lua.clone();
+    /// Load a schema from a chunk compiled by this runtime.
+    ///
+    /// Luau does not verify bytecode, so never load a chunk from an untrusted source.
+    pub fn load_compiled(&self, chunk: &CompiledChunk, name: &str) -> Result<Schema, crate::Error> {
+        self.load_compiled_with_secrets(chunk, name, &Secrets::default())
+    }
+
+    /// Load a schema from a compiled chunk, like [`Runtime::load_with_secrets`].
+    pub fn load_compiled_with_secrets(
+        &self,
+        chunk: &CompiledChunk,
+        name: &str,
+        secrets: &Secrets,
+    ) -> Result<Schema, crate::Error> {
+        let result = self
+            .lua
+            .load(&chunk.bytecode[..])
+            .set_name(format!("={}", name))
+            .set_mode(mlua::ChunkMode::Binary)
+            .set_environment(self.create_environment(secrets)?)
+            .eval()
+            .map_err(crate::Error::LuaLoadError)?;
+        let mut schema = Schema::load_with_spec(&self.lua, &chunk.source, result, &self.info_spec)?;
+        schema.secrets = secrets.clone();
+        Ok(schema)
+    }
+
+    /// Load the new code of an already loaded schema, with the secrets it was loaded with.
+    ///
+    /// Fails with [`crate::Error::SchemaIdMismatch`] if the new code declares a different id.
+    pub fn reload(&self, old: &Schema, new_code: &str) -> Result<Schema, crate::Error> {
+        let schema = self.load_with_secrets(new_code, &old.schema_info.name, &old.secrets)?;
+        if schema.schema_info.id != old.schema_info.id {
+            return Err(crate::Error::SchemaIdMismatch {
+                expected: old.schema_info.id,
+                found: schema.schema_info.id,
+            });
+        }
+        Ok(schema)
+    }
+
+    /// Load every `.lua` file of a `.tar.gz` bundle, paired with its file name.
+    ///
+    /// A file that fails to load does not stop the others. Errors reading the archive fail
+    /// with [`crate::Error::IoError`].
+    pub fn load_bundle(
+        &self,
+        reader: impl Read,
+    ) -> Result<LoadResults<String, Schema>, crate::Error> {
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+        let mut schemas = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?;
+            if !entry.header().entry_type().is_file()
+                || path.extension().is_none_or(|extension| extension != "lua")
+            {
+                continue;
+            }
+            let Some(name) = path.file_name().map(|name| name.to_string_lossy().into_owned())
+            else {
+                continue;
+            };
+            let mut code = String::new();
+            let schema = match entry.read_to_string(&mut code) {
+                Ok(_) => self.load(&code, &name),
+                Err(e) => Err(e.into()),
+            };
+            schemas.push((name, schema));
+        }
+        Ok(schemas)
+    }
+
+    /// Load every `.lua` file directly inside a directory, sorted by path, to check a
+    /// collection of schemas in bulk.
+    ///
+    /// Each file fails like [`Runtime::load`] would, without stopping the others. Loading is
+    /// all the checking a schema gets without its site: it rejects invalid metadata, a
+    /// missing command and a command without its `page` or `parse` function, while
+    /// [`Schema::validate`] needs a sample page to run `parse` on. Errors reading the
+    /// directory fail with [`crate::Error::IoError`].
+    pub fn validate_dir(
+        &self,
+        dir: impl AsRef<Path>,
+    ) -> Result<LoadResults<PathBuf, SchemaInfo>, crate::Error> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|extension| extension == "lua") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let results = paths
+            .into_iter()
+            .map(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                let info = std::fs::read_to_string(&path)
+                    .map_err(crate::Error::from)
+                    .and_then(|code| self.load(&code, &name))
+                    .map(|schema| schema.schema_info);
+                (path, info)
+            })
+            .collect();
+        Ok(results)
+    }
+
+    fn create_environment(&self, secrets: &Secrets) -> mlua::Result<mlua::Table> {
+        Self::environment(&self.lua, self.prelude.clone(), Some(secrets))
+    }
+
+    fn environment(
+        lua: &Arc<mlua::Lua>,
+        prelude: Option<Arc<str>>,
+        secrets: Option<&Secrets>,
+    ) -> mlua::Result<mlua::Table> {
+        let env = lua.create_table()?;
+        let globals = lua.globals();
+        let metatable = match globals.metatable() {
+            Some(metatable) => metatable,
+            // an unsandboxed runtime has no proxy metatable over the globals
+            None => lua.create_table_from([("__index", globals)])?,
+        };
+        env.set_metatable(Some(metatable));
+        let require_lua = lua.clone();
         env.raw_set(
             "require",
-            self.lua
-                .create_function(move |_, name: String| Self::environment_require(&name, &lua))?,
+            lua.create_function(move |_, name: String| {
+                Self::environment_require(&name, &require_lua, prelude.as_deref())
+            })?,
         )?;
+        if let Some(secrets) = secrets {
+            let secrets = lua.create_table_from(secrets.0.clone())?;
+            secrets.set_readonly(true);
+            env.raw_set("secrets", secrets)?;
+        }
         env.set_readonly(true);
         Ok(env)
     }
-    #[instrument(skip(lua))]
-    fn environment_require(name: &str, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+    #[instrument(skip(lua, prelude))]
+    fn environment_require(
+        name: &str,
+        lua: &Arc<mlua::Lua>,
+        prelude: Option<&str>,
+    ) -> mlua::Result<mlua::Value> {
         let global = lua.globals();
         let package: mlua::Table = global.get("package")?;
         let loaded: mlua::Table = package.get("loaded")?;
@@ -74,6 +335,25 @@ impl Runtime {
         if !name.starts_with('@') {
             return Err(mlua::Error::RuntimeError(format!("invalid module name: {}, you can only import pre-defined modules that start with @", name)));
         }
+        if let (PRELUDE_MODULE, Some(prelude)) = (name, prelude) {
+            let required: mlua::Value = lua
+                .load(prelude)
+                .set_name(format!("={}", PRELUDE_MODULE))
+                .set_environment(Self::environment(lua, None, None)?)
+                .eval()?;
+            loaded.set(name, required.clone())?;
+            return Ok(required);
+        }
+        if name == META_MODULE {
+            let meta = lua.create_table()?;
+            meta.set(
+                "packages",
+                lua.create_function(|_, ()| Ok(Self::available_packages()))?,
+            )?;
+            meta.set_readonly(true);
+            loaded.set(name, meta.clone())?;
+            return Ok(mlua::Value::Table(meta));
+        }
         let package_name = &name[1..];
         if let Some(module) = Self::get_predefined_package(package_name) {
             let required = module.create_instance(lua)?;
@@ -93,7 +373,7 @@ impl Runtime {
 
 #[cfg(test)]
 mod tests {
-    use crate::hashset;
+    use std::collections::BTreeSet;
 
     use super::*;
 
@@ -131,15 +411,451 @@ return {
         assert_eq!(schema.schema_info.lh_version, "1.0");
         assert_eq!(
             schema.schema_info.legal_domains,
-            hashset!["test.com".to_string()]
+            BTreeSet::from(["test.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_reload() {
+        let script = |id: &str, name: &str| {
+            format!(
+                r#"--@id: {id}
+--@name: {name}
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test() end
+return {{
+    search = {{page = test, parse = test}},
+    book_info = {{page = test, parse = test}},
+    toc = {{page = test, parse = test}},
+    chapter = {{page = test, parse = test}},
+}}
+"#
+            )
+        };
+        let runtime = Runtime::new();
+        let old = runtime
+            .load(&script("198ca153-ccae-4f82-9218-9b6657796b57", "old"), "test")
+            .unwrap();
+        let new = runtime
+            .reload(&old, &script("198ca153-ccae-4f82-9218-9b6657796b57", "new"))
+            .unwrap();
+        assert_eq!(new.schema_info.id, old.schema_info.id);
+        assert_eq!(new.schema_info.name, "new");
+
+        let result = runtime.reload(&old, &script("2f1c4a9e-5b7d-4c3a-8e6f-0a1b2c3d4e5f", "other"));
+        assert!(matches!(
+            result,
+            Err(crate::Error::SchemaIdMismatch { expected, .. }) if expected == old.schema_info.id
+        ));
+    }
+
+    #[test]
+    fn test_compile() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test() end
+local function page(keyword, page)
+    return "https://test.com/search?q=" .. keyword .. "&page=" .. page
+end
+return {
+    search = {page = page, parse = test},
+    book_info = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+}
+"#;
+        let runtime = Runtime::new();
+        let chunk = runtime.compile(script).unwrap();
+        let fresh = runtime.load(script, "fresh").unwrap();
+        assert_eq!(chunk.fingerprint(), fresh.fingerprint());
+        assert!(Arc::ptr_eq(&runtime.compile(script).unwrap().bytecode, &chunk.bytecode));
+
+        let compiled = runtime.load_compiled(&chunk, "compiled").unwrap();
+        assert_eq!(compiled.schema_info.id, fresh.schema_info.id);
+        assert_eq!(compiled.schema_info.raw_fields(), fresh.schema_info.raw_fields());
+        assert_eq!(compiled.fingerprint(), fresh.fingerprint());
+        let request = |schema: &Schema| {
+            let params = crate::schema::PageParams {
+                page: 2,
+                ..Default::default()
+            };
+            schema
+                .resolve_request(crate::schema::CommandKind::Search, "abc", params, None)
+                .unwrap()
+                .unwrap()
+                .url
+        };
+        assert_eq!(request(&compiled), request(&fresh));
+        assert_eq!(request(&compiled), "https://test.com/search?q=abc&page=2");
+
+        assert!(runtime.compile("return {").is_err());
+    }
+
+    #[test]
+    fn test_secrets() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test() end
+local function page(keyword)
+    return {url = "https://test.com/search?q=" .. keyword .. "&key=" .. secrets.api_key}
+end
+return {
+    search = {page = page, parse = test},
+    book_info = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+}
+"#;
+        let runtime = Runtime::new();
+        let secrets = Secrets::from(HashMap::from([(
+            "api_key".to_string(),
+            "s3cr3t".to_string(),
+        )]));
+        let schema = runtime.load_with_secrets(script, "test", &secrets).unwrap();
+        let request = schema
+            .resolve_request(
+                crate::schema::CommandKind::Search,
+                "abc",
+                Default::default(),
+                None,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.url, "https://test.com/search?q=abc&key=s3cr3t");
+
+        let logged = format!("{:?}", secrets);
+        assert!(logged.contains("api_key"));
+        assert!(!logged.contains("s3cr3t"));
+
+        let err = runtime
+            .load_with_secrets("secrets.api_key = 'x'", "write", &secrets)
+            .unwrap_err();
+        assert!(err.to_string().contains("readonly"));
+
+        // secrets are not shared with schemas loaded without them
+        let other = runtime.load(script, "other").unwrap();
+        assert!(
+            other
+                .resolve_request(
+                    crate::schema::CommandKind::Search,
+                    "abc",
+                    Default::default(),
+                    None,
+                )
+                .is_err()
         );
+
+        let search = |schema: &Schema| {
+            schema
+                .resolve_request(
+                    crate::schema::CommandKind::Search,
+                    "abc",
+                    Default::default(),
+                    None,
+                )
+                .unwrap()
+                .unwrap()
+                .url
+        };
+        let reloaded = runtime.reload(&schema, script).unwrap();
+        assert_eq!(search(&reloaded), "https://test.com/search?q=abc&key=s3cr3t");
+        let chunk = runtime.compile(script).unwrap();
+        let compiled = runtime
+            .load_compiled_with_secrets(&chunk, "compiled", &secrets)
+            .unwrap();
+        assert_eq!(search(&compiled), "https://test.com/search?q=abc&key=s3cr3t");
+    }
+
+    #[test]
+    fn test_load_bundle() {
+        let script = |id: &str, name: &str| {
+            format!(
+                r#"--@id: {id}
+--@name: {name}
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test() end
+return {{
+    search = {{page = test, parse = test}},
+    book_info = {{page = test, parse = test}},
+    toc = {{page = test, parse = test}},
+    chapter = {{page = test, parse = test}},
+}}
+"#
+            )
+        };
+        let files = [
+            ("schemas/a.lua", script("198ca153-ccae-4f82-9218-9b6657796b57", "a")),
+            ("schemas/b.lua", script("2f1c4a9e-5b7d-4c3a-8e6f-0a1b2c3d4e5f", "b")),
+            ("schemas/README.md", "not a schema".to_string()),
+        ];
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, content) in &files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        let bundle = builder.into_inner().unwrap().finish().unwrap();
+
+        let runtime = Runtime::new();
+        let schemas = runtime.load_bundle(bundle.as_slice()).unwrap();
+        let names: Vec<_> = schemas
+            .into_iter()
+            .map(|(file, schema)| (file, schema.unwrap().schema_info.name))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("a.lua".to_string(), "a".to_string()),
+                ("b.lua".to_string(), "b".to_string())
+            ]
+        );
+
+        let result = runtime.load_bundle(&b"not a bundle"[..]);
+        assert!(matches!(result, Err(crate::Error::IoError(_))));
+    }
+
+    #[test]
+    fn test_validate_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let good = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: good
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test() end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+}
+"#;
+        let missing_toc = good
+            .replace("--@name: good", "--@name: broken")
+            .replace("    toc = {page = test, parse = test},\n", "");
+        std::fs::write(dir.join("a.lua"), good).unwrap();
+        std::fs::write(dir.join("b.lua"), missing_toc).unwrap();
+        std::fs::write(dir.join("c.lua"), "--@name: no id\nreturn {}").unwrap();
+        let missing_parse = good.replace(
+            "chapter = {page = test, parse = test}",
+            "chapter = {page = test}",
+        );
+        std::fs::write(dir.join("d.lua"), missing_parse).unwrap();
+        std::fs::write(dir.join("README.md"), "not a schema").unwrap();
+
+        let results = Runtime::new().validate_dir(dir).unwrap();
+        let files: Vec<_> = results
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(files, ["a.lua", "b.lua", "c.lua", "d.lua"]);
+        assert_eq!(results[0].1.as_ref().unwrap().name, "good");
+        assert!(matches!(results[1].1, Err(crate::Error::ScriptParseError(_))));
+        assert!(matches!(results[2].1, Err(crate::Error::ScriptParseError(_))));
+        assert!(results[3].1.is_err());
+
+        assert!(Runtime::new().validate_dir(dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_load_error() {
+        let runtime = Runtime::new();
+        let error = runtime
+            .load(
+                r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local x = = 1
+"#,
+                "test",
+            )
+            .unwrap_err();
+        assert!(matches!(error, crate::Error::LuaLoadError(_)));
+        assert_eq!(error.lua_line(), Some(8));
+    }
+
+    #[test]
+    fn test_load_wrong_shape() {
+        let header = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test() end
+"#;
+        let runtime = Runtime::new();
+        for body in [
+            "return test",
+            "return nil",
+            "return {search = {page = test, parse = test}, book_info = test}",
+        ] {
+            let error = runtime.load(&format!("{}{}", header, body), "test").unwrap_err();
+            match error {
+                crate::Error::ScriptParseError(message) => assert_eq!(
+                    message,
+                    "schema must return a table with fields: search, book_info, chapter, toc"
+                ),
+                error => panic!("unexpected error: {}", error),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runtime_error() {
+        let runtime = Runtime::new();
+        let schema = runtime
+            .load(
+                r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test() end
+local function book_info(id)
+    local book = nil
+    return book.url
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = test},
+    toc = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+}
+"#,
+                "test",
+            )
+            .unwrap();
+        let http = crate::http::HttpClient::new(reqwest::Client::new(), Default::default());
+        let error = schema.book_info("1", &http, None, None).await.unwrap_err();
+        assert!(matches!(error, crate::Error::LuaError(_)));
+        assert_eq!(error.lua_line(), Some(11));
+        assert!(error.lua_traceback().is_some());
+    }
+
+    #[test]
+    fn test_prelude() {
+        let runtime = Runtime::new().with_prelude(
+            r#"
+            local prelude = {}
+            function prelude.trim(s)
+                return (string.gsub(s, "^%s*(.-)%s*$", "%1"))
+            end
+            return prelude
+        "#,
+        );
+        let schema = runtime
+            .load(
+                r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local prelude = require('@prelude')
+assert(prelude.trim("  title  ") == "title")
+local function test() end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+}
+"#,
+                "test",
+            )
+            .unwrap();
+        assert_eq!(schema.schema_info.name, "test_schema");
+
+        let runtime = Runtime::new();
+        let result = runtime
+            .lua
+            .load(r#"require('@prelude')"#)
+            .set_environment(runtime.create_environment(&Secrets::default()).unwrap())
+            .exec();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_available_packages() {
+        let packages = Runtime::available_packages();
+        #[cfg(feature = "pkg-json")]
+        assert!(packages.contains(&"json"));
+        #[cfg(not(feature = "pkg-json"))]
+        assert!(!packages.contains(&"json"));
+
+        let runtime = Runtime::new();
+        let listed: Vec<String> = runtime
+            .lua
+            .load(r#"return require('@meta').packages()"#)
+            .set_environment(runtime.create_environment(&Secrets::default()).unwrap())
+            .eval()
+            .unwrap();
+        assert_eq!(listed, packages);
+    }
+
+    #[test]
+    fn test_trusted() {
+        let code = r#"
+            string.shout = function(s) return string.upper(s) .. "!" end
+            assert(string.shout("title") == "TITLE!")
+        "#;
+        let runtime = Runtime::trusted();
+        runtime
+            .lua
+            .load(code)
+            .set_environment(runtime.create_environment(&Secrets::default()).unwrap())
+            .exec()
+            .unwrap();
+
+        let runtime = Runtime::new();
+        let result = runtime
+            .lua
+            .load(code)
+            .set_environment(runtime.create_environment(&Secrets::default()).unwrap())
+            .exec();
+        assert!(result.is_err());
     }
 
     #[test]
     #[cfg(feature = "pkg-json")]
     fn test_require() {
         let runtime = Runtime::new();
-        let env = runtime.create_environment().unwrap();
+        let env = runtime.create_environment(&Secrets::default()).unwrap();
         runtime
             .lua
             .load(
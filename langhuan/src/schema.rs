@@ -1,32 +1,53 @@
 use crate::{
-    http::{HttpClient, HttpRequest},
+    http::{HttpClient, HttpRequest, HttpResponse, LegalDomain},
     Result,
 };
+use futures::{StreamExt, stream};
 use mlua::{FromLua, IntoLua, LuaSerdeExt, Table};
-use std::{collections::HashSet, str::FromStr};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::ControlFlow,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
 use tracing::error;
 
 mod book_info;
 mod chapter;
+mod diff;
 mod info_parser;
+mod normalize;
 mod search;
+mod self_test;
 mod session;
+mod sign;
 mod toc;
+mod validate;
 
 pub use book_info::*;
 pub use chapter::*;
+pub use diff::*;
+pub use normalize::*;
 pub use search::*;
+pub use self_test::{TestCase, TestReport};
 pub use session::*;
+pub use sign::{SignAlgorithm, SignScheme};
 pub use toc::*;
+pub use validate::{ValidationIssue, ValidationReport};
 
 impl FromLua for HttpRequest {
     fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
         if let mlua::Value::String(url) = value {
             Ok(HttpRequest {
                 url: url.to_str()?.to_string(),
-                method: Default::default(),
-                headers: Default::default(),
-                body: Default::default(),
+                ..Default::default()
             })
         } else {
             lua.from_value(value)
@@ -34,16 +55,223 @@ impl FromLua for HttpRequest {
     }
 }
 
+/// Absent fields become `null`, see [`LuaRequest`] to leave them `nil`.
 impl IntoLua for HttpRequest {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        LuaRequest {
+            request: self,
+            none_as_null: true,
+        }
+        .into_lua(lua)
+    }
+}
+
+/// A request passed to Lua with a chosen representation of its absent fields.
+pub(crate) struct LuaRequest {
+    pub(crate) request: HttpRequest,
+    /// `null` if set, otherwise the fields are left `nil`
+    pub(crate) none_as_null: bool,
+}
+
+impl IntoLua for LuaRequest {
     fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
         let options = mlua::SerializeOptions::new()
-            .serialize_none_to_null(true)
+            .serialize_none_to_null(self.none_as_null)
             .serialize_unit_to_null(true)
             .set_array_metatable(false);
-        lua.to_value_with(&self, options)
+        lua.to_value_with(&self.request, options)
+    }
+}
+
+/// The result of a `parse` function, which may ask for another request instead of content.
+///
+/// A parse function requests a refetch by returning `{ __refetch = request }`.
+#[derive(Debug)]
+pub enum Parsed<T> {
+    Content(T),
+    Refetch(HttpRequest),
+}
+
+impl<T> Parsed<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Parsed<U> {
+        match self {
+            Parsed::Content(content) => Parsed::Content(f(content)),
+            Parsed::Refetch(request) => Parsed::Refetch(request),
+        }
+    }
+
+    pub fn into_content(self) -> Option<T> {
+        match self {
+            Parsed::Content(content) => Some(content),
+            Parsed::Refetch(_) => None,
+        }
+    }
+}
+
+impl<T: FromLua> FromLua for Parsed<T> {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        if let mlua::Value::Table(table) = &value
+            && let Some(request) = table.get::<Option<HttpRequest>>("__refetch")?
+        {
+            return Ok(Parsed::Refetch(request));
+        }
+        let type_name = value.type_name();
+        T::from_lua(value, lua)
+            .map(Parsed::Content)
+            .map_err(|e| match e {
+                e @ mlua::Error::FromLuaConversionError { .. } => e,
+                e => mlua::Error::FromLuaConversionError {
+                    from: type_name,
+                    to: std::any::type_name::<T>().into(),
+                    message: Some(e.to_string()),
+                },
+            })
+    }
+}
+
+/// See [`Schema::fingerprint`].
+pub(crate) fn fingerprint(source: &str) -> String {
+    #[cfg(feature = "fingerprint-blake3")]
+    let hash = blake3::hash(source.as_bytes()).as_bytes().to_vec();
+    #[cfg(not(feature = "fingerprint-blake3"))]
+    let hash = {
+        use sha2::Digest;
+        sha2::Sha256::digest(source.as_bytes()).to_vec()
+    };
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn canonical_id<'a>(pattern: Option<&regex::Regex>, id: &'a str) -> &'a str {
+    pattern
+        .and_then(|pattern| pattern.captures(id))
+        .and_then(|captures| captures.get(1))
+        .map_or(id, |id| id.as_str())
+}
+
+/// The maximum number of refetches a single parse is allowed to request.
+const MAX_REFETCH: usize = 1;
+
+/// Fetch the page and parse it, following refetch requests from the parse function.
+///
+/// Every request holds a permit of `permits`, if given, while it is in flight.
+///
+/// Returns the parsed content, the body it was parsed from and the url it was fetched from.
+async fn fetch_parsed<T>(
+    http: &HttpClient,
+    permits: Option<&Semaphore>,
+    request: HttpRequest,
+    parse: impl Fn(String, &str) -> Result<Parsed<T>>,
+) -> Result<(T, String, String)> {
+    let mut request = request;
+    for _ in 0..=MAX_REFETCH {
+        let url = request.url.clone();
+        let permit = match permits {
+            Some(permits) => Some(permits.acquire().await.expect("semaphore is never closed")),
+            None => None,
+        };
+        let (content, final_url) = http.request_page(request).await?;
+        drop(permit);
+        match parse(content.clone(), &final_url)? {
+            Parsed::Content(parsed) => return Ok((parsed, content, url)),
+            Parsed::Refetch(next) => request = next,
+        }
+    }
+    Err(refetch_exceeded())
+}
+
+fn refetch_exceeded() -> crate::Error {
+    crate::Error::ScriptParseError(format!(
+        "parse requested more than {} refetch",
+        MAX_REFETCH
+    ))
+}
+
+/// The parameters passed to the `page` function of a paginated command.
+#[derive(Debug, Clone, Default)]
+pub struct PageParams {
+    pub page: u64,
+    /// the body of the previous page
+    pub content: Option<String>,
+    /// the cursor returned by the parse of the previous page
+    pub cursor: Option<String>,
+}
+
+/// The progress of a [`PageItems`], saved with [`PageItems::to_cursor`] to continue the
+/// fetching later with [`Schema::resume_toc`] or [`PageItems::with_cursor`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageCursor {
+    /// the number of the next page to fetch
+    pub page: u64,
+    /// the number of the first page, see [`PageItems::with_page_base`]
+    pub page_base: u64,
+    /// the body of the last page fetched
+    pub page_content: Option<String>,
+    pub cursor: Option<String>,
+    pub next_page_url: Option<String>,
+    pub finished: bool,
+    pub total: Option<u64>,
+}
+
+/// The pagination signals a paginated `parse` function may return as its second value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PageInfo {
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    /// the total number of items, as reported by the site
+    #[serde(default)]
+    pub total: Option<u64>,
+    /// the url of the next page, linked from the page itself
+    #[serde(default)]
+    pub next_page_url: Option<String>,
+    /// whether the site reports a further page; `false` ends the pagination
+    #[serde(default)]
+    pub has_next: Option<bool>,
+    /// the number of pages, see [`PageItems::fetch_all_parallel`]
+    #[serde(default)]
+    pub total_pages: Option<u64>,
+}
+
+/// Where a fetched page stands in the pagination, see [`PageItems::last_meta`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageMeta {
+    /// the number of the page, starting at the [page base](SchemaInfo::page_base)
+    pub page: u64,
+    /// whether `next_page` may return a further page
+    pub has_next: bool,
+    /// the total number of items reported by the first page
+    pub total: Option<u64>,
+}
+
+impl FromLua for PageInfo {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Nil => Ok(PageInfo::default()),
+            value => lua.from_value(value),
+        }
     }
 }
 
+/// The most chars of the body kept in [`PageDebug::excerpt`].
+#[cfg(feature = "debug")]
+pub const DEBUG_EXCERPT_CHARS: usize = 200;
+
+/// What was fetched for a page, see [`PageItems::next_page_debug`].
+#[cfg(feature = "debug")]
+#[derive(Debug, Clone)]
+pub struct PageDebug {
+    /// the url the page was fetched from, after any refetch
+    pub url: String,
+    /// the length of the body in bytes
+    pub body_len: usize,
+    /// the beginning of the body
+    pub excerpt: String,
+}
+
+/// The content of a page that carries pagination signals.
+pub trait Paginated {
+    fn page_info(&self) -> &PageInfo;
+}
+
 pub trait CommandRequest {
     fn wrap(self, map: impl FnOnce(HttpRequest) -> Result<HttpRequest>) -> Result<Self>
     where
@@ -71,6 +299,64 @@ impl CommandRequest for Option<HttpRequest> {
     }
 }
 
+/// The difference between the declared legal domains and the domains actually requested.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainAudit {
+    /// declared domains that were never requested
+    pub unused: Vec<String>,
+    /// requested domains that are not declared
+    pub undeclared: Vec<String>,
+}
+
+/// What a host needs to list a schema, see [`Schema::descriptor`].
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaDescriptor {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    pub lh_version: String,
+    pub legal_domains: Vec<String>,
+    pub capabilities: Vec<&'static str>,
+}
+
+/// The commands a schema provides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandKind {
+    Search,
+    BookInfo,
+    Toc,
+    Chapter,
+}
+
+impl CommandKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandKind::Search => "search",
+            CommandKind::BookInfo => "book_info",
+            CommandKind::Toc => "toc",
+            CommandKind::Chapter => "chapter",
+        }
+    }
+
+    /// Report a `parse` result of the wrong type as a [`crate::Error::ScriptParseError`]
+    /// naming this command.
+    fn parse_error(self, error: mlua::Error) -> crate::Error {
+        match error {
+            mlua::Error::FromLuaConversionError { from, to, message } => {
+                crate::Error::ScriptParseError(format!(
+                    "{} parse returned a {} value where {} was expected{}",
+                    self.as_str(),
+                    from,
+                    to,
+                    message.map(|m| format!(": {}", m)).unwrap_or_default()
+                ))
+            }
+            error => error.into(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Schema {
     pub schema_info: SchemaInfo,
@@ -79,16 +365,66 @@ pub struct Schema {
     book_chapter: ChapterCommand,
     book_toc: TocCommand,
     session: Option<SessionCommand>,
+    /// applied to every fetched body before it reaches a command's `parse`
+    preprocess: Option<mlua::Function>,
+    parse_timeout: Option<Duration>,
+    tests: self_test::SelfTests,
+    /// bounds the requests in flight by `--@max-concurrency`
+    permits: Option<Arc<Semaphore>>,
+    signing_secret: Option<Vec<u8>>,
+    none_as_null: bool,
+    /// the secrets the schema was loaded with, kept for [`crate::runtime::Runtime::reload`]
+    pub(crate) secrets: crate::runtime::Secrets,
+    lua: mlua::Lua,
+}
+
+/// The commands every schema must define.
+const COMMAND_KEYS: [&str; 4] = ["search", "book_info", "chapter", "toc"];
+
+/// Check that the script returned a table of commands, before reading its fields.
+fn check_shape(value: mlua::Value) -> Result<Table> {
+    let shape_error = || {
+        crate::Error::ScriptParseError(format!(
+            "schema must return a table with fields: {}",
+            COMMAND_KEYS.join(", ")
+        ))
+    };
+    let mlua::Value::Table(table) = value else {
+        return Err(shape_error());
+    };
+    for key in COMMAND_KEYS {
+        if !matches!(table.get(key)?, mlua::Value::Table(_)) {
+            return Err(shape_error());
+        }
+    }
+    Ok(table)
 }
 
 impl Schema {
-    pub fn load(script: &str, table: Table) -> Result<Self> {
-        let schema_info = SchemaInfo::from_str(script)?;
+    /// Load the schema from the value returned by its script.
+    pub fn load(lua: &mlua::Lua, script: &str, value: mlua::Value) -> Result<Self> {
+        Self::load_with_spec(lua, script, value, &SchemaInfoSpec::default())
+    }
+
+    /// Load the schema, requiring the metadata fields of the spec.
+    pub fn load_with_spec(
+        lua: &mlua::Lua,
+        script: &str,
+        value: mlua::Value,
+        spec: &SchemaInfoSpec,
+    ) -> Result<Self> {
+        let schema_info = SchemaInfo::parse_with_spec(script, spec)?;
+        let table = check_shape(value)?;
         let book_search = table.get("search")?;
         let book_info = table.get("book_info")?;
         let book_chapter = table.get("chapter")?;
         let book_toc = table.get("toc")?;
         let session = table.get("session")?;
+        let preprocess = table.get("preprocess")?;
+        let tests = table.get("tests")?;
+        let permits = schema_info
+            .max_concurrency
+            .map(|max| Arc::new(Semaphore::new(max)));
         Ok(Schema {
             schema_info,
             book_search,
@@ -96,29 +432,416 @@ impl Schema {
             book_chapter,
             book_toc,
             session,
+            preprocess,
+            parse_timeout: None,
+            tests,
+            permits,
+            signing_secret: None,
+            none_as_null: true,
+            secrets: Default::default(),
+            lua: lua.clone(),
         })
     }
 
+    /// Run the samples declared in the `tests` table of the schema against the site.
+    ///
+    /// The table lists samples as `book_info = { { id = ..., title = ... } }` and
+    /// `search = { { keyword = ..., title = ... } }`, where a search sample checks the title
+    /// of the first result.
+    pub async fn self_test(&self, http: &HttpClient) -> Result<TestReport> {
+        let mut report = TestReport::default();
+        for sample in &self.tests.book_info {
+            let failure = match self.book_info(&sample.id, http, None, None).await {
+                Ok(info) => self_test::check_title(
+                    &sample.title,
+                    info.as_ref().map(|info| info.title.as_str()),
+                ),
+                Err(e) => Some(e.to_string()),
+            };
+            report.cases.push(TestCase {
+                command: CommandKind::BookInfo,
+                input: sample.id.clone(),
+                failure,
+            });
+        }
+        for sample in &self.tests.search {
+            let failure = match self.search_first(&sample.keyword, http, None, None).await {
+                Ok(item) => self_test::check_title(
+                    &sample.title,
+                    item.as_ref().map(|item| item.title.as_str()),
+                ),
+                Err(e) => Some(e.to_string()),
+            };
+            report.cases.push(TestCase {
+                command: CommandKind::Search,
+                input: sample.keyword.clone(),
+                failure,
+            });
+        }
+        Ok(report)
+    }
+
+    /// Run the `parse` function of a command on a sample body and check what it produces.
+    ///
+    /// Unlike loading the values, which falls back to defaults, this flags empty critical
+    /// fields such as `id` and `title`. Parse errors, including values of the wrong type,
+    /// are reported as issues too. The body has no url, so `parse` gets an empty one.
+    pub fn validate(&self, command: CommandKind, body: &str) -> ValidationReport {
+        let mut report = ValidationReport::new(command);
+        let body = body.to_string();
+        match command {
+            CommandKind::Search => report.check_items(
+                self.command(&self.book_search, None).parse(body, ""),
+                validate::search_item_fields,
+            ),
+            CommandKind::BookInfo => report.check_value(
+                self.book_command(&self.book_info, None).parse(body, ""),
+                validate::book_info_fields,
+            ),
+            CommandKind::Toc => report.check_items(
+                self.book_command(&self.book_toc, None).parse(body, ""),
+                validate::toc_item_fields,
+            ),
+            CommandKind::Chapter => report.check_items(
+                self.command(&self.book_chapter, None).parse(body, ""),
+                validate::paragraph_fields,
+            ),
+        }
+        report
+    }
+
+    /// Create a session by running the session command.
+    ///
+    /// The `parse` function of the session receives the response headers, with lowercase
+    /// names, as its second argument. Returns `None` if the schema has no session command.
+    pub async fn login(&self, http: &HttpClient) -> Result<Option<Session>> {
+        let Some(session) = &self.session else {
+            return Ok(None);
+        };
+        let request = session.page("", ())?;
+        self.warm_up(http).await?;
+        Self::finish_login(session, http, request).await.map(Some)
+    }
+
+    /// Create a session by running a multi-step login, e.g. fetching a form for its csrf
+    /// token before posting the credentials.
+    ///
+    /// The request of `page` is followed by one request per function of the session's
+    /// `steps` list, each built from the `(body, headers)` of the previous response, and
+    /// the last response is passed to `parse`. Without `steps`, this is [`Schema::login`].
+    pub async fn login_with_steps(&self, http: &HttpClient) -> Result<Option<Session>> {
+        let Some(session) = &self.session else {
+            return Ok(None);
+        };
+        let mut request = session.page("", ())?;
+        self.warm_up(http).await?;
+        for step in &session.steps {
+            let response = http.request_full(request).await?;
+            request = session.step(step, response)?;
+        }
+        Self::finish_login(session, http, request).await.map(Some)
+    }
+
+    /// Request the `--@warmup-url` page, once per client.
+    async fn warm_up(&self, http: &HttpClient) -> Result<()> {
+        match &self.schema_info.warmup_url {
+            Some(url) => http.warm_up(url).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Send the last login request and parse the session, following refetch requests.
+    async fn finish_login(
+        session: &SessionCommand,
+        http: &HttpClient,
+        request: HttpRequest,
+    ) -> Result<Session> {
+        let mut request = request;
+        for _ in 0..=MAX_REFETCH {
+            let response = http.request_full(request).await?;
+            match session.parse_response(response)? {
+                Parsed::Content(session) => return Ok(session),
+                Parsed::Refetch(next) => request = next,
+            }
+        }
+        Err(refetch_exceeded())
+    }
+
+    /// Build a session from a token the host already has, e.g. one saved by a previous run,
+    /// without running the session command.
+    pub fn with_session(&self, token: serde_json::Value) -> Result<Session> {
+        let options = mlua::SerializeOptions::new()
+            .serialize_none_to_null(false)
+            .serialize_unit_to_null(false)
+            .set_array_metatable(false);
+        Ok(self.lua.to_value_with(&token, options)?)
+    }
+
+    fn command<'a, C>(
+        &'a self,
+        command: &'a C,
+        session: Option<Session>,
+    ) -> CommandWithSession<'a, 'a, C> {
+        CommandWithSession::new(command, self.session.as_ref(), session)
+            .with_preprocess(self.preprocess.as_ref())
+            .with_parse_timeout(&self.lua, self.parse_timeout)
+            .with_signature(self.schema_info.sign.as_ref(), self.signing_secret.as_deref())
+            .with_none_as_null(self.none_as_null)
+            .with_session_required(self.schema_info.requires_session)
+    }
+
+    /// A command taking a book id, which is canonicalized by `--@id-pattern`.
+    fn book_command<'a, C>(
+        &'a self,
+        command: &'a C,
+        session: Option<Session>,
+    ) -> CommandWithSession<'a, 'a, C> {
+        self.command(command, session)
+            .with_id_pattern(self.schema_info.id_pattern.as_ref())
+    }
+
     pub fn search<'a, 'b, 'c>(
         &'a self,
         keyword: &'b str,
         http: &'c HttpClient,
         session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
     ) -> PageItems<'b, 'c, CommandWithSession<'a, 'a, SearchCommand>> {
-        let command = CommandWithSession::new(&self.book_search, self.session.as_ref(), session);
+        let command = self
+            .command(&self.book_search, session)
+            .with_extra_headers(extra_headers);
         PageItems::new(command, keyword, http)
+            .with_permits(self.permits.clone())
+            .with_warmup(self.schema_info.warmup_url.clone())
+            .with_page_base(self.schema_info.page_base)
+    }
+
+    /// Abort `parse` functions that run longer than the timeout, failing with
+    /// [`crate::Error::ResourceLimit`].
+    ///
+    /// Only the call of `parse` is limited, not the iterators it returns.
+    pub fn with_parse_timeout(mut self, timeout: Duration) -> Self {
+        ParseDeadlines::install(&self.lua);
+        self.parse_timeout = Some(timeout);
+        self
+    }
+
+    /// The secret the requests are signed with, for a schema declaring `--@sign`.
+    pub fn with_signing_secret(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.signing_secret = Some(secret.into());
+        self
+    }
+
+    /// Pass the absent fields of a request to the session's `wrap` as `null`, the default,
+    /// or leave them `nil` for `wrap` functions checking e.g. `request.json == nil`.
+    pub fn with_none_as_null(mut self, none_as_null: bool) -> Self {
+        self.none_as_null = none_as_null;
+        self
+    }
+
+    /// Normalize the whitespace of chapter text and book intros, which are kept raw by default.
+    pub fn with_text_normalization(mut self, enabled: bool) -> Self {
+        self.book_info.normalize_text = enabled;
+        self.book_chapter.normalize_text = enabled;
+        self
+    }
+
+    /// The optional features the schema provides, besides the four commands every schema has.
+    pub fn capabilities(&self) -> Vec<&'static str> {
+        let mut capabilities = Vec::new();
+        if let Some(session) = &self.session {
+            capabilities.push("session");
+            if !session.steps.is_empty() {
+                capabilities.push("login_steps");
+            }
+        }
+        if self.preprocess.is_some() {
+            capabilities.push("preprocess");
+        }
+        if self.schema_info.sign.is_some() {
+            capabilities.push("sign");
+        }
+        if !self.tests.book_info.is_empty() || !self.tests.search.is_empty() {
+            capabilities.push("self_test");
+        }
+        capabilities
+    }
+
+    /// A serializable summary of the schema for a catalog, without any Lua value.
+    pub fn descriptor(&self) -> SchemaDescriptor {
+        let info = &self.schema_info;
+        SchemaDescriptor {
+            id: info.id,
+            name: info.name.clone(),
+            author: info.author.clone(),
+            description: info.description.clone(),
+            lh_version: info.lh_version.clone(),
+            legal_domains: info.legal_domains.iter().cloned().collect(),
+            capabilities: self.capabilities(),
+        }
+    }
+
+    /// Clean up the tags of toc items, which are kept raw by default.
+    pub fn with_tag_normalization(mut self, normalization: TagNormalization) -> Self {
+        self.book_toc.tag_normalization = Some(normalization);
+        self
+    }
+
+    /// Resolve the request a command would send, including the session wrapping, without
+    /// fetching it.
+    ///
+    /// `params` is ignored for [`CommandKind::BookInfo`].
+    pub fn resolve_request(
+        &self,
+        command: CommandKind,
+        id: &str,
+        params: PageParams,
+        session: Option<Session>,
+    ) -> Result<Option<HttpRequest>> {
+        match command {
+            CommandKind::Search => self.command(&self.book_search, session).page(id, params),
+            CommandKind::BookInfo => self
+                .book_command(&self.book_info, session)
+                .page(id, ())
+                .map(Some),
+            CommandKind::Toc => self.book_command(&self.book_toc, session).page(id, params),
+            CommandKind::Chapter => self.command(&self.book_chapter, session).page(id, params),
+        }
+    }
+
+    /// Compare the declared legal domains against the domains requested during a test run.
+    ///
+    /// This is a linting aid for schema authors and does not restrict any request.
+    pub fn audit_domains(&self, requested: &[String]) -> DomainAudit {
+        let declared: BTreeSet<(&str, &str)> = self
+            .schema_info
+            .legal_domains
+            .iter()
+            .map(|entry| {
+                let host = LegalDomain::parse(entry).map_or(entry.as_str(), |legal| legal.host);
+                (host, entry.as_str())
+            })
+            .collect();
+        let requested: BTreeSet<&str> = requested.iter().map(String::as_str).collect();
+        DomainAudit {
+            unused: declared
+                .iter()
+                .filter(|(host, _)| !requested.contains(host))
+                .map(|(_, entry)| entry.to_string())
+                .collect(),
+            undeclared: requested
+                .iter()
+                .filter(|domain| !declared.iter().any(|(host, _)| host == *domain))
+                .map(|domain| domain.to_string())
+                .collect(),
+        }
+    }
+
+    /// Search and return the first item of the first page, without fetching further pages.
+    pub async fn search_first(
+        &self,
+        keyword: &str,
+        http: &HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> Result<Option<SearchItem>> {
+        let mut items = self.search(keyword, http, session, extra_headers);
+        match items.next_page().await? {
+            Some(mut iter) => iter.next().transpose(),
+            None => Ok(None),
+        }
+    }
+
+    /// The web page of a book, filled from `--@book-url-template` with its canonical id.
+    pub fn book_url(&self, id: &str) -> Option<String> {
+        self.schema_info
+            .book_url_template
+            .as_ref()
+            .map(|template| template.replace("{id}", self.schema_info.canonical_id(id)))
+    }
+
+    /// The original script of the schema.
+    pub fn source(&self) -> &str {
+        &self.schema_info.source
+    }
+
+    /// A hex encoded hash of the script source, which changes whenever the script is edited.
+    ///
+    /// The hash is sha256, or blake3 with the `fingerprint-blake3` feature.
+    pub fn fingerprint(&self) -> String {
+        fingerprint(self.source())
     }
 
+    /// Fetch the book info.
+    ///
+    /// `None` if the book does not exist, which `parse` tells by returning `nil`, while a
+    /// page it fails to parse is an error.
+    ///
+    /// [`BookInfo::chapter_count`] is `None` unless the site reports it on the book page.
     pub async fn book_info(
         &self,
         id: &str,
         http: &HttpClient,
         session: Option<Session>,
-    ) -> Result<BookInfo> {
-        let command = CommandWithSession::new(&self.book_info, self.session.as_ref(), session);
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> Result<Option<BookInfo>> {
+        let command = self
+            .book_command(&self.book_info, session)
+            .with_extra_headers(extra_headers);
         let path = command.page(id, ())?;
-        let content = http.request(path).await?;
-        command.parse(content)
+        self.warm_up(http).await?;
+        let permits = self.permits.as_deref();
+        let (info, ..) =
+            fetch_parsed(http, permits, path, |content, url| command.parse(content, url)).await?;
+        Ok(info)
+    }
+
+    /// Fetch the book info of many books, at most `concurrency` at a time.
+    ///
+    /// The results are in the order they complete, and a failed book does not stop the
+    /// others.
+    pub async fn book_info_batch(
+        &self,
+        ids: &[&str],
+        http: &HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+        concurrency: usize,
+    ) -> Vec<(String, Result<Option<BookInfo>>)> {
+        stream::iter(ids)
+            .map(|id| {
+                let session = session.clone();
+                let extra_headers = extra_headers.clone();
+                async move {
+                    let info = self.book_info(id, http, session, extra_headers).await;
+                    (id.to_string(), info)
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    pub async fn book_info_with_toc(
+        &self,
+        id: &str,
+        http: &HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> Result<Option<BookInfoWithToc>> {
+        let command = self
+            .book_command(&self.book_info, session)
+            .with_extra_headers(extra_headers);
+        let path = command.page(id, ())?;
+        self.warm_up(http).await?;
+        let (info, ..) = fetch_parsed(http, self.permits.as_deref(), path, |content, url| {
+            command.parse_with(content, |content| {
+                let normalization = self.book_toc.tag_normalization;
+                self.book_info.parse_with_toc(content, url, normalization)
+            })
+        })
+        .await?;
+        Ok(info)
     }
 
     pub fn chapter<'a, 'b, 'c>(
@@ -126,9 +849,35 @@ impl Schema {
         id: &'b str,
         http: &'c HttpClient,
         session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
     ) -> PageItems<'b, 'c, CommandWithSession<'a, 'a, ChapterCommand>> {
-        let command = CommandWithSession::new(&self.book_chapter, self.session.as_ref(), session);
+        let command = self
+            .command(&self.book_chapter, session)
+            .with_extra_headers(extra_headers);
         PageItems::new(command, id, http)
+            .with_permits(self.permits.clone())
+            .with_warmup(self.schema_info.warmup_url.clone())
+            .with_page_base(self.schema_info.page_base)
+    }
+
+    /// Fetch a whole chapter, chaining the paragraphs of all its parts.
+    ///
+    /// For sites serving a chapter as several fragments: `page` is called with the part
+    /// number, starting at 1, until it returns `nil`.
+    pub async fn chapter_content(
+        &self,
+        id: &str,
+        http: &HttpClient,
+        session: Option<Session>,
+    ) -> Result<ChapterContent> {
+        let mut parts = self.chapter(id, http, session, None);
+        let mut paragraphs = Vec::new();
+        while let Some(part) = parts.next_page().await? {
+            for paragraph in part {
+                paragraphs.push(paragraph?);
+            }
+        }
+        Ok(ChapterContent { paragraphs })
     }
 
     pub fn toc<'a, 'b, 'c>(
@@ -136,9 +885,27 @@ impl Schema {
         id: &'b str,
         http: &'c HttpClient,
         session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
     ) -> PageItems<'b, 'c, CommandWithSession<'a, 'a, TocCommand>> {
-        let command = CommandWithSession::new(&self.book_toc, self.session.as_ref(), session);
+        let command = self
+            .book_command(&self.book_toc, session)
+            .with_extra_headers(extra_headers);
         PageItems::new(command, id, http)
+            .with_permits(self.permits.clone())
+            .with_warmup(self.schema_info.warmup_url.clone())
+            .with_page_base(self.schema_info.page_base)
+    }
+
+    /// Continue fetching the toc from a cursor saved by [`PageItems::to_cursor`].
+    pub fn resume_toc<'a, 'b, 'c>(
+        &'a self,
+        cursor: PageCursor,
+        id: &'b str,
+        http: &'c HttpClient,
+        session: Option<Session>,
+        extra_headers: Option<HashMap<String, String>>,
+    ) -> PageItems<'b, 'c, CommandWithSession<'a, 'a, TocCommand>> {
+        self.toc(id, http, session, extra_headers).with_cursor(cursor)
     }
 }
 
@@ -149,19 +916,71 @@ pub struct SchemaInfo {
     pub author: String,
     pub description: String,
     pub lh_version: String,
-    pub legal_domains: HashSet<String>,
+    /// kept sorted so that iteration and debug output are stable
+    pub legal_domains: BTreeSet<String>,
+    /// the encoding of the site, used when a response does not declare one
+    pub charset: Option<String>,
+    /// extracts the canonical book id from the first capture group
+    pub id_pattern: Option<regex::Regex>,
+    /// the most requests the site accepts at the same time
+    pub max_concurrency: Option<usize>,
+    /// how the requests are signed
+    pub sign: Option<SignScheme>,
+    /// a page requested before the first request, e.g. for the cookies it sets
+    pub warmup_url: Option<String>,
+    /// the longest response body the site legitimately serves, to apply with
+    /// [`HttpClient::with_max_body_bytes`]
+    pub max_body_bytes: Option<usize>,
+    /// the web page of a book, with `{id}` in place of its id
+    pub book_url_template: Option<String>,
+    /// whether every command needs a session, see [`crate::Error::SessionRequired`]
+    pub requires_session: bool,
+    /// the number of the first page passed to `page`, 1 unless the site counts from 0
+    pub page_base: u64,
+    source: String,
 }
 
-impl FromStr for SchemaInfo {
-    type Err = crate::Error;
+/// Which metadata fields a script has to declare, the `id` always is.
+///
+/// An optional field that is not declared is left empty. The default spec requires all of
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaInfoSpec {
+    pub name: bool,
+    pub author: bool,
+    pub description: bool,
+    pub lh_version: bool,
+}
 
-    fn from_str(s: &str) -> Result<Self> {
+impl Default for SchemaInfoSpec {
+    fn default() -> Self {
+        Self {
+            name: true,
+            author: true,
+            description: true,
+            lh_version: true,
+        }
+    }
+}
+
+impl SchemaInfo {
+    /// Parse the metadata of a script, requiring the fields of the spec.
+    pub fn parse_with_spec(s: &str, spec: &SchemaInfoSpec) -> Result<Self> {
         let mut id = None;
         let mut name = None;
         let mut author = None;
         let mut description = None;
         let mut lh_version = None;
-        let mut legal_domains = HashSet::new();
+        let mut legal_domains = BTreeSet::new();
+        let mut charset = None;
+        let mut id_pattern = None;
+        let mut max_concurrency = None;
+        let mut sign = None;
+        let mut warmup_url = None;
+        let mut max_body_bytes = None;
+        let mut book_url_template = None;
+        let mut requires_session = false;
+        let mut page_base = 1;
         for line in info_parser::parse_script(s) {
             let line = line?;
             match line.name {
@@ -173,6 +992,67 @@ impl FromStr for SchemaInfo {
                 "legal-domains" => {
                     legal_domains.insert(line.value.to_string());
                 }
+                "charset" => {
+                    if encoding_rs::Encoding::for_label(line.value.as_bytes()).is_none() {
+                        return Err(crate::Error::ScriptParseError(format!(
+                            "invalid charset: {}",
+                            line.value
+                        )));
+                    }
+                    charset = Some(line.value.to_string());
+                }
+                "id-pattern" => {
+                    let pattern = regex::Regex::new(line.value).map_err(|e| {
+                        crate::Error::ScriptParseError(format!("invalid id-pattern: {}", e))
+                    })?;
+                    if pattern.captures_len() < 2 {
+                        return Err(crate::Error::ScriptParseError(
+                            "id-pattern needs a capture group".to_string(),
+                        ));
+                    }
+                    id_pattern = Some(pattern);
+                }
+                "sign" => sign = Some(line.value.parse()?),
+                "warmup-url" => warmup_url = Some(line.value.to_string()),
+                "max-body-bytes" => {
+                    max_body_bytes = Some(line.value.parse().map_err(|_| {
+                        crate::Error::ScriptParseError(format!(
+                            "invalid max-body-bytes: {}",
+                            line.value
+                        ))
+                    })?);
+                }
+                "book-url-template" => {
+                    if !line.value.contains("{id}") {
+                        return Err(crate::Error::ScriptParseError(format!(
+                            "book-url-template needs an {{id}}: {}",
+                            line.value
+                        )));
+                    }
+                    book_url_template = Some(line.value.to_string());
+                }
+                "requires-session" => {
+                    requires_session = line.value.parse().map_err(|_| {
+                        crate::Error::ScriptParseError(format!(
+                            "invalid requires-session: {}",
+                            line.value
+                        ))
+                    })?;
+                }
+                "page-base" => {
+                    page_base = line.value.parse().map_err(|_| {
+                        crate::Error::ScriptParseError(format!("invalid page-base: {}", line.value))
+                    })?;
+                }
+                "max-concurrency" => match line.value.parse::<usize>() {
+                    Ok(max) if max > 0 => max_concurrency = Some(max),
+                    _ => {
+                        return Err(crate::Error::ScriptParseError(format!(
+                            "invalid max-concurrency: {}",
+                            line.value
+                        )));
+                    }
+                },
                 _ => {
                     return Err(crate::Error::ScriptParseError(format!(
                         "unknown field in the script: {}",
@@ -181,6 +1061,11 @@ impl FromStr for SchemaInfo {
                 }
             }
         }
+        let field = |value: Option<&str>, field_name: &str, required: bool| match value {
+            Some(value) => Ok(value.to_owned()),
+            None if !required => Ok(String::new()),
+            None => Err(crate::Error::ScriptParseError(format!("missing field: {}", field_name))),
+        };
         Ok(SchemaInfo {
             id: id
                 .ok_or_else(|| crate::Error::ScriptParseError("missing field: id".to_string()))
@@ -188,33 +1073,79 @@ impl FromStr for SchemaInfo {
                     uuid::Uuid::parse_str(id)
                         .map_err(|e| crate::Error::ScriptParseError(e.to_string()))
                 })?,
-            name: name
-                .map(|name| name.to_owned())
-                .ok_or_else(|| crate::Error::ScriptParseError("missing field: name".to_string()))?,
-            author: author.map(|author| author.to_owned()).ok_or_else(|| {
-                crate::Error::ScriptParseError("missing field: author".to_string())
-            })?,
-            description: description
-                .map(|description| description.to_owned())
-                .ok_or_else(|| {
-                    crate::Error::ScriptParseError("missing field: description".to_string())
-                })?,
-            lh_version: lh_version
-                .map(|lh_version| lh_version.to_owned())
-                .ok_or_else(|| {
-                    crate::Error::ScriptParseError("missing field: lh-version".to_string())
-                })?,
+            name: field(name, "name", spec.name)?,
+            author: field(author, "author", spec.author)?,
+            description: field(description, "description", spec.description)?,
+            lh_version: field(lh_version, "lh-version", spec.lh_version)?,
             legal_domains,
+            charset,
+            id_pattern,
+            max_concurrency,
+            sign,
+            warmup_url,
+            max_body_bytes,
+            book_url_template,
+            requires_session,
+            page_base,
+            source: s.to_string(),
         })
     }
+
+    /// The metadata fields in the order they are declared in the script.
+    pub fn raw_fields(&self) -> Vec<(String, String)> {
+        info_parser::parse_script(&self.source)
+            .map_while(|field| field.ok())
+            .map(|field| (field.name.to_string(), field.value.to_string()))
+            .collect()
+    }
+
+    /// The legal domains in a stable order, for display.
+    pub fn legal_domains_sorted(&self) -> Vec<&str> {
+        self.legal_domains.iter().map(String::as_str).collect()
+    }
+
+    /// The canonical form of a book id, as used by `book_info` and `toc`.
+    ///
+    /// The id is kept untouched if no `--@id-pattern` is declared or the pattern does not
+    /// match it.
+    pub fn canonical_id<'a>(&self, id: &'a str) -> &'a str {
+        canonical_id(self.id_pattern.as_ref(), id)
+    }
+
+    /// The encoding declared by `--@charset`.
+    pub fn encoding(&self) -> Option<&'static encoding_rs::Encoding> {
+        self.charset
+            .as_deref()
+            .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()))
+    }
+}
+
+impl FromStr for SchemaInfo {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SchemaInfo::parse_with_spec(s, &SchemaInfoSpec::default())
+    }
 }
+
 pub trait Command {
     type Request: CommandRequest;
     type Page;
     type RequestParams;
     type PageContent;
     fn page(&self, id: &str, params: Self::RequestParams) -> Result<Self::Request>;
-    fn parse(&self, content: Self::Page) -> Result<Self::PageContent>;
+
+    /// Parse the page, passing `final_url`, where the request landed after redirects, to
+    /// the `parse` function as its second argument.
+    fn parse(&self, content: Self::Page, final_url: &str) -> Result<Parsed<Self::PageContent>>;
+
+    /// The request for a `next_page_url` returned by `parse`, in place of `page`.
+    fn follow(&self, url: String) -> Result<HttpRequest> {
+        Ok(HttpRequest {
+            url,
+            ..Default::default()
+        })
+    }
 }
 
 impl<C> Command for &C
@@ -230,8 +1161,79 @@ where
         (*self).page(id, params)
     }
 
-    fn parse(&self, content: C::Page) -> Result<C::PageContent> {
-        (*self).parse(content)
+    fn parse(&self, content: C::Page, final_url: &str) -> Result<Parsed<C::PageContent>> {
+        (*self).parse(content, final_url)
+    }
+
+    fn follow(&self, url: String) -> Result<HttpRequest> {
+        (*self).follow(url)
+    }
+}
+
+/// The deadline of a parse in progress, and whether the interrupt stopped it.
+struct ParseDeadline {
+    at: Instant,
+    timed_out: Arc<AtomicBool>,
+}
+
+/// The deadlines of the parses in progress, innermost last, by the thread running them.
+///
+/// The schemas of a runtime share one Lua, which runs the parses of several threads in
+/// turn, so a single interrupt checks the deadline of the thread it interrupts.
+#[derive(Default)]
+struct ParseDeadlines(Mutex<HashMap<ThreadId, Vec<ParseDeadline>>>);
+
+impl ParseDeadlines {
+    /// Install the interrupt checking the deadlines, once per Lua.
+    fn install(lua: &mlua::Lua) {
+        if lua.app_data_ref::<Self>().is_some() {
+            return;
+        }
+        lua.set_app_data(Self::default());
+        lua.set_interrupt(|lua| {
+            let Some(deadlines) = lua.app_data_ref::<Self>() else {
+                return Ok(mlua::VmState::Continue);
+            };
+            let deadlines = deadlines.0.lock().expect("parse deadlines lock poisoned");
+            if let Some(deadline) = deadlines
+                .get(&thread::current().id())
+                .and_then(|deadlines| deadlines.last())
+                && Instant::now() >= deadline.at
+            {
+                deadline.timed_out.store(true, Ordering::Relaxed);
+                return Err(mlua::Error::runtime("parse timed out"));
+            }
+            Ok(mlua::VmState::Continue)
+        });
+    }
+
+    fn with<T>(
+        lua: &mlua::Lua,
+        f: impl FnOnce(&mut HashMap<ThreadId, Vec<ParseDeadline>>) -> T,
+    ) -> T {
+        let deadlines = lua
+            .app_data_ref::<Self>()
+            .expect("parse deadlines installed by Schema::with_parse_timeout");
+        let mut deadlines = deadlines.0.lock().expect("parse deadlines lock poisoned");
+        f(&mut deadlines)
+    }
+
+    fn push(lua: &mlua::Lua, deadline: ParseDeadline) {
+        Self::with(lua, |deadlines| {
+            deadlines.entry(thread::current().id()).or_default().push(deadline)
+        });
+    }
+
+    fn pop(lua: &mlua::Lua) {
+        Self::with(lua, |deadlines| {
+            let id = thread::current().id();
+            if let Some(stack) = deadlines.get_mut(&id) {
+                stack.pop();
+                if stack.is_empty() {
+                    deadlines.remove(&id);
+                }
+            }
+        });
     }
 }
 
@@ -240,6 +1242,14 @@ pub struct CommandWithSession<'a, 'b, C> {
     command: &'a C,
     session_command: Option<&'b SessionCommand>,
     session: Option<Session>,
+    preprocess: Option<&'b mlua::Function>,
+    id_pattern: Option<&'b regex::Regex>,
+    parse_timeout: Option<(&'b mlua::Lua, Duration)>,
+    sign: Option<&'b SignScheme>,
+    signing_secret: Option<&'b [u8]>,
+    none_as_null: bool,
+    session_required: bool,
+    extra_headers: Option<HashMap<String, String>>,
 }
 
 impl<'a, 'b, C> CommandWithSession<'a, 'b, C> {
@@ -252,42 +1262,173 @@ impl<'a, 'b, C> CommandWithSession<'a, 'b, C> {
             command,
             session_command,
             session,
+            preprocess: None,
+            id_pattern: None,
+            parse_timeout: None,
+            sign: None,
+            signing_secret: None,
+            none_as_null: true,
+            session_required: false,
+            extra_headers: None,
         }
     }
-}
 
-impl<C, R> Command for CommandWithSession<'_, '_, C>
-where
-    C: Command<Request = R>,
-    R: CommandRequest,
-{
-    type Page = C::Page;
-    type PageContent = C::PageContent;
-    type Request = C::Request;
-    type RequestParams = C::RequestParams;
+    /// Fail every request with [`crate::Error::SessionRequired`] if there is no session.
+    pub fn with_session_required(mut self, required: bool) -> Self {
+        self.session_required = required;
+        self
+    }
 
-    fn page(&self, id: &str, params: C::RequestParams) -> Result<C::Request> {
-        let path = self.command.page(id, params)?;
-        path.wrap(|request| {
-            if let (Some(session_command), Some(session)) = (self.session_command, &self.session) {
-                session_command.wrap(request, session.clone())
-            } else {
-                Ok(request)
+    /// Add the headers to every request that does not set them itself, on top of the
+    /// default headers of the client.
+    pub fn with_extra_headers(mut self, extra_headers: Option<HashMap<String, String>>) -> Self {
+        self.extra_headers = extra_headers;
+        self
+    }
+
+    fn check_session(&self) -> Result<()> {
+        if self.session_required && self.session.is_none() {
+            return Err(crate::Error::SessionRequired);
+        }
+        Ok(())
+    }
+
+    /// Pass the absent fields of a request to the session's `wrap` as `null`, the default,
+    /// or leave them `nil`.
+    pub fn with_none_as_null(mut self, none_as_null: bool) -> Self {
+        self.none_as_null = none_as_null;
+        self
+    }
+
+    /// Sign every request with the scheme, after the session is attached.
+    pub fn with_signature(
+        mut self,
+        sign: Option<&'b SignScheme>,
+        signing_secret: Option<&'b [u8]>,
+    ) -> Self {
+        self.sign = sign;
+        self.signing_secret = signing_secret;
+        self
+    }
+
+    /// Abort the Lua code run by `parse` once the timeout elapses.
+    pub fn with_parse_timeout(mut self, lua: &'b mlua::Lua, timeout: Option<Duration>) -> Self {
+        self.parse_timeout = timeout.map(|timeout| (lua, timeout));
+        self
+    }
+
+    /// Canonicalize the id with the pattern before passing it to `page`.
+    pub fn with_id_pattern(mut self, id_pattern: Option<&'b regex::Regex>) -> Self {
+        self.id_pattern = id_pattern;
+        self
+    }
+
+    /// Pass every page through the schema's `preprocess` function before parsing it.
+    pub fn with_preprocess(mut self, preprocess: Option<&'b mlua::Function>) -> Self {
+        self.preprocess = preprocess;
+        self
+    }
+
+    /// Add the extra headers, let the session command attach the session to the request,
+    /// then sign it.
+    fn prepare_request(&self, mut request: HttpRequest) -> Result<HttpRequest> {
+        for (name, value) in self.extra_headers.iter().flatten() {
+            let present = request.headers.keys().any(|key| key.eq_ignore_ascii_case(name));
+            if !present {
+                request.headers.insert(name.clone(), value.clone());
             }
-        })
+        }
+        let request = match (self.session_command, &self.session) {
+            (Some(session_command), Some(session)) => {
+                session_command.wrap_with(request, session.clone(), self.none_as_null)?
+            }
+            _ => request,
+        };
+        match (self.sign, self.signing_secret) {
+            (Some(sign), Some(secret)) => sign.sign(request, secret),
+            (Some(_), None) => Err(crate::SchemaError::InvalidRequest(
+                "the schema signs its requests but no signing secret is set".to_string(),
+            ))?,
+            (None, _) => Ok(request),
+        }
+    }
+
+    /// Preprocess the page and parse it with `parse`, under the parse timeout.
+    fn parse_with<T>(&self, content: String, parse: impl FnOnce(String) -> Result<T>) -> Result<T> {
+        let run = || {
+            let content = match self.preprocess {
+                Some(preprocess) => preprocess.call(content)?,
+                None => content,
+            };
+            parse(content)
+        };
+        let Some((lua, timeout)) = self.parse_timeout else {
+            return run();
+        };
+        let timed_out = Arc::new(AtomicBool::new(false));
+        ParseDeadlines::push(
+            lua,
+            ParseDeadline {
+                at: Instant::now() + timeout,
+                timed_out: timed_out.clone(),
+            },
+        );
+        let result = run();
+        ParseDeadlines::pop(lua);
+        match result {
+            Err(_) if timed_out.load(Ordering::Relaxed) => Err(crate::Error::ResourceLimit(
+                format!("parse ran longer than {:?}", timeout),
+            )),
+            result => result,
+        }
+    }
+}
+
+impl<C, R> Command for CommandWithSession<'_, '_, C>
+where
+    C: Command<Request = R, Page = String>,
+    R: CommandRequest,
+{
+    type Page = C::Page;
+    type PageContent = C::PageContent;
+    type Request = C::Request;
+    type RequestParams = C::RequestParams;
+
+    fn page(&self, id: &str, params: C::RequestParams) -> Result<C::Request> {
+        self.check_session()?;
+        let path = self.command.page(canonical_id(self.id_pattern, id), params)?;
+        path.wrap(|request| self.prepare_request(request))
+    }
+
+    fn parse(&self, content: C::Page, final_url: &str) -> Result<Parsed<C::PageContent>> {
+        self.parse_with(content, |content| self.command.parse(content, final_url))
     }
 
-    fn parse(&self, content: C::Page) -> Result<C::PageContent> {
-        self.command.parse(content)
+    fn follow(&self, url: String) -> Result<HttpRequest> {
+        self.check_session()?;
+        self.prepare_request(self.command.follow(url)?)
     }
 }
 
+/// The pages of a paginated command, fetched one at a time.
+///
+/// No task is spawned: every request runs inside the future of
+/// [`next_page`](PageItems::next_page), so dropping that future, or the `PageItems` with
+/// it, aborts the request in flight and releases its connection right away.
 pub struct PageItems<'a, 'b, C> {
     command: C,
     id: &'a str,
     page: u64,
+    page_base: u64,
     page_content: Option<String>,
+    cursor: Option<String>,
+    next_page_url: Option<String>,
+    finished: bool,
+    total: Option<u64>,
     http: &'b HttpClient,
+    permits: Option<Arc<Semaphore>>,
+    warmup: Option<String>,
+    last_meta: Option<PageMeta>,
 }
 
 impl<'a, 'b, C> PageItems<'a, 'b, C> {
@@ -296,20 +1437,149 @@ impl<'a, 'b, C> PageItems<'a, 'b, C> {
             command,
             id,
             page: 1,
+            page_base: 1,
             page_content: None,
+            cursor: None,
+            next_page_url: None,
+            finished: false,
+            total: None,
             http,
+            permits: None,
+            warmup: None,
+            last_meta: None,
+        }
+    }
+
+    /// The pagination metadata of the last page fetched.
+    pub fn last_meta(&self) -> Option<&PageMeta> {
+        self.last_meta.as_ref()
+    }
+
+    /// Hold a permit of the semaphore while each page is requested.
+    pub fn with_permits(mut self, permits: Option<Arc<Semaphore>>) -> Self {
+        self.permits = permits;
+        self
+    }
+
+    /// Start counting the pages at `base` rather than 1, for sites counting from 0.
+    pub fn with_page_base(mut self, base: u64) -> Self {
+        self.page = base;
+        self.page_base = base;
+        self
+    }
+
+    /// Warm the client up with the page before the first request, see
+    /// [`HttpClient::warm_up`].
+    pub fn with_warmup(mut self, url: Option<String>) -> Self {
+        self.warmup = url;
+        self
+    }
+
+    /// The total number of items reported by the first page, if the schema provided one.
+    pub fn estimated_total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Save the progress, to continue with the next page later.
+    pub fn to_cursor(&self) -> PageCursor {
+        PageCursor {
+            page: self.page,
+            page_base: self.page_base,
+            page_content: self.page_content.clone(),
+            cursor: self.cursor.clone(),
+            next_page_url: self.next_page_url.clone(),
+            finished: self.finished,
+            total: self.total,
         }
     }
+
+    /// Continue from a saved [`PageCursor`], fetching the page after the last one it saw.
+    ///
+    /// The command must be the one the cursor was saved from, for the same id.
+    pub fn resume(cursor: PageCursor, command: C, id: &'a str, http: &'b HttpClient) -> Self {
+        Self::new(command, id, http).with_cursor(cursor)
+    }
+
+    /// Continue from a saved [`PageCursor`], keeping the permits and warm-up already set.
+    pub fn with_cursor(mut self, cursor: PageCursor) -> Self {
+        self.restore(cursor);
+        self
+    }
+
+    fn restore(&mut self, cursor: PageCursor) {
+        self.page = cursor.page;
+        self.page_base = cursor.page_base;
+        self.page_content = cursor.page_content;
+        self.cursor = cursor.cursor;
+        self.next_page_url = cursor.next_page_url;
+        self.finished = cursor.finished;
+        self.total = cursor.total;
+    }
 }
 
 impl<C> PageItems<'_, '_, C>
 where
-    C: Command<RequestParams = (u64, Option<String>), Request = Option<HttpRequest>, Page = String>,
+    C: Command<RequestParams = PageParams, Request = Option<HttpRequest>, Page = String>,
+    C::PageContent: Paginated,
 {
+    /// Fetch the next page.
+    ///
+    /// Once a page has been requested with a cursor, a page whose parse returns no
+    /// `next_cursor` is the last one. Likewise, a `next_page_url` is requested as is,
+    /// bypassing `page`, and a linked page that links to no further page is the last one.
+    ///
+    /// Cancel safe: the progress is only updated once the page has been fetched, so if the
+    /// future is dropped before it completes, the request is aborted and the next call
+    /// fetches the same page again.
     pub async fn next_page(&mut self) -> Result<Option<C::PageContent>> {
-        let request = self
-            .command
-            .page(self.id, (self.page, self.page_content.take()));
+        Ok(self.fetch_next().await?.map(|(page, _)| page))
+    }
+
+    /// Fetch the next page like [`PageItems::next_page`], along with its [`PageMeta`].
+    pub async fn next_page_with_meta(&mut self) -> Result<Option<(C::PageContent, PageMeta)>> {
+        let Some((page, _)) = self.fetch_next().await? else {
+            return Ok(None);
+        };
+        let meta = self.last_meta.clone().expect("set by a fetched page");
+        Ok(Some((page, meta)))
+    }
+
+    /// Fetch the next page like [`PageItems::next_page`], along with what was fetched.
+    ///
+    /// Meant for schema authors telling an empty result from a page that was not the
+    /// expected one.
+    #[cfg(feature = "debug")]
+    pub async fn next_page_debug(&mut self) -> Result<Option<(C::PageContent, PageDebug)>> {
+        let Some((page, url)) = self.fetch_next().await? else {
+            return Ok(None);
+        };
+        let body = self.page_content.as_deref().unwrap_or_default();
+        let debug = PageDebug {
+            url,
+            body_len: body.len(),
+            excerpt: body.chars().take(DEBUG_EXCERPT_CHARS).collect(),
+        };
+        Ok(Some((page, debug)))
+    }
+
+    /// Fetch the next page and the url it was fetched from.
+    async fn fetch_next(&mut self) -> Result<Option<(C::PageContent, String)>> {
+        if self.finished {
+            return Ok(None);
+        }
+        // nothing is taken before the response, so a dropped fetch leaves the progress as is
+        let params = PageParams {
+            page: self.page,
+            content: self.page_content.clone(),
+            cursor: self.cursor.clone(),
+        };
+        let with_cursor = params.cursor.is_some();
+        let linked = self.next_page_url.clone();
+        let with_link = linked.is_some();
+        let request = match linked {
+            Some(url) => self.command.follow(url).map(Some),
+            None => self.command.page(self.id, params),
+        };
         match request {
             Err(e) => {
                 error!("get page({}) failed: {}", self.page, e);
@@ -317,20 +1587,181 @@ where
             }
             Ok(None) => Ok(None),
             Ok(Some(request)) => {
-                let response = self.http.request(request).await?;
-                let iter = self.command.parse(response.clone())?;
+                if let Some(url) = &self.warmup {
+                    self.http.warm_up(url).await?;
+                }
+                let permits = self.permits.as_deref();
+                let (iter, response, url) =
+                    fetch_parsed(self.http, permits, request, |content, final_url| {
+                        self.command.parse(content, final_url)
+                    })
+                    .await?;
+                if self.page == self.page_base {
+                    self.total = iter.page_info().total;
+                }
+                let page_info = iter.page_info();
+                self.cursor = page_info.next_cursor.clone();
+                self.next_page_url = page_info.next_page_url.clone();
+                self.finished = (with_cursor && self.cursor.is_none())
+                    || (with_link && self.next_page_url.is_none())
+                    || page_info.has_next == Some(false);
+                self.last_meta = Some(PageMeta {
+                    page: self.page,
+                    has_next: !self.finished,
+                    total: self.total,
+                });
                 self.page_content = Some(response);
                 self.page += 1;
-                Ok(Some(iter))
+                Ok(Some((iter, url)))
+            }
+        }
+    }
+
+    /// Collect the items of all remaining pages.
+    ///
+    /// Stops without an error when the pages run out (or a page is empty), when `limit`
+    /// items have been collected or when `timeout` elapses, returning what was gathered.
+    /// The page in flight when `timeout` elapses is fetched again by the next call.
+    pub async fn collect_all<T>(&mut self, limit: usize, timeout: Duration) -> Result<Vec<T>>
+    where
+        C::PageContent: Iterator<Item = Result<T>>,
+    {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut items = Vec::new();
+        while items.len() < limit {
+            let page = match tokio::time::timeout_at(deadline, self.next_page()).await {
+                Ok(page) => page?,
+                Err(_) => break,
+            };
+            let Some(page) = page else {
+                break;
+            };
+            let count = items.len();
+            for item in page.take(limit - count) {
+                items.push(item?);
+            }
+            if items.len() == count {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Pass the items of all remaining pages to `f` as they are fetched, holding no more
+    /// than one page in memory.
+    ///
+    /// Stops when `f` returns [`ControlFlow::Break`], without fetching another page, or
+    /// when the pages run out (or a page is empty).
+    pub async fn for_each_item<T, F>(mut self, mut f: F) -> Result<()>
+    where
+        C::PageContent: Iterator<Item = Result<T>>,
+        F: FnMut(Result<T>) -> ControlFlow<()>,
+    {
+        while let Some(page) = self.next_page().await? {
+            let mut empty = true;
+            for item in page {
+                empty = false;
+                if f(item).is_break() {
+                    return Ok(());
+                }
+            }
+            if empty {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch the items of all remaining pages, requesting the pages after the next one
+    /// `concurrency` at a time once it reports `total_pages`.
+    ///
+    /// Those pages are requested with `page` and their number alone, without the previous
+    /// page or a cursor, and `page` must return a request for each of them. Without
+    /// `total_pages` the pages are fetched one after the other. Either way the items are in
+    /// page order.
+    ///
+    /// If any page fails, the progress is left as it was before the call, so that it can be
+    /// retried.
+    pub async fn fetch_all_parallel<T>(&mut self, concurrency: usize) -> Result<Vec<T>>
+    where
+        C::PageContent: Iterator<Item = Result<T>>,
+    {
+        let saved = self.to_cursor();
+        let result = self.fetch_all_pages(concurrency).await;
+        if result.is_err() {
+            self.restore(saved);
+        }
+        result
+    }
+
+    async fn fetch_all_pages<T>(&mut self, concurrency: usize) -> Result<Vec<T>>
+    where
+        C::PageContent: Iterator<Item = Result<T>>,
+    {
+        let Some(first) = self.next_page().await? else {
+            return Ok(Vec::new());
+        };
+        let total_pages = first.page_info().total_pages;
+        let mut items = first.collect::<Result<Vec<T>>>()?;
+        let Some(total_pages) = total_pages else {
+            while let Some(page) = self.next_page().await? {
+                let count = items.len();
+                for item in page {
+                    items.push(item?);
+                }
+                if items.len() == count {
+                    break;
+                }
             }
+            return Ok(items);
+        };
+        if self.finished {
+            return Ok(items);
+        }
+        let end = self.page_base + total_pages;
+        let requests = (self.page..end)
+            .map(|page| {
+                let params = PageParams {
+                    page,
+                    ..Default::default()
+                };
+                self.command.page(self.id, params)?.ok_or_else(|| {
+                    crate::Error::ScriptParseError(format!(
+                        "page returned nil for page {} of {} total pages",
+                        page, total_pages
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (command, http) = (&self.command, self.http);
+        let permits = self.permits.as_deref();
+        let pages: Vec<Result<Vec<T>>> = stream::iter(requests)
+            .map(|request| async move {
+                let (page, ..) =
+                    fetch_parsed(http, permits, request, |content, url| command.parse(content, url))
+                        .await?;
+                page.collect()
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await;
+        for page in pages {
+            items.extend(page?);
         }
+        self.finished = true;
+        self.page = end;
+        Ok(items)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::hashset;
+    use crate::{hashset, tests::mock_url};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path, query_param},
+    };
 
     #[test]
     fn test_schema_info() {
@@ -354,8 +1785,114 @@ mod tests {
         assert_eq!(schema_info.lh_version, "1.0");
         assert_eq!(
             schema_info.legal_domains,
-            hashset!["test.com".to_string(), "test2.com".to_string()]
+            BTreeSet::from(["test.com".to_string(), "test2.com".to_string()])
+        );
+        let field = |name: &str, value: &str| (name.to_string(), value.to_string());
+        assert_eq!(
+            schema_info.raw_fields(),
+            vec![
+                field("id", "198ca153-ccae-4f82-9218-9b6657796b57"),
+                field("name", "test_schema"),
+                field("author", "test_author"),
+                field("description", "test"),
+                field("lh-version", "1.0"),
+                field("legal-domains", "test.com"),
+                field("legal-domains", "test2.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_legal_domains_sorted() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+--@legal-domains: test2.com
+--@legal-domains: cdn.test.com
+
+"#;
+        let schema_info = SchemaInfo::from_str(script).unwrap();
+        assert_eq!(
+            schema_info.legal_domains_sorted(),
+            ["cdn.test.com", "test.com", "test2.com"]
+        );
+    }
+
+    #[test]
+    fn test_schema_info_spec() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+"#;
+        let result = SchemaInfo::from_str(script);
+        assert!(matches!(
+            result,
+            Err(crate::Error::ScriptParseError(message)) if message == "missing field: description"
+        ));
+        let strict = SchemaInfoSpec::default();
+        assert!(SchemaInfo::parse_with_spec(script, &strict).is_err());
+
+        let spec = SchemaInfoSpec {
+            description: false,
+            ..Default::default()
+        };
+        let schema_info = SchemaInfo::parse_with_spec(script, &spec).unwrap();
+        assert_eq!(schema_info.description, "");
+        assert_eq!(schema_info.name, "test_schema");
+        let without_name = script.replace("--@name: test_schema\n", "");
+        assert!(SchemaInfo::parse_with_spec(&without_name, &spec).is_err());
+
+        let code = format!(
+            "{}local function test() end\nreturn {{search = {{page = test, parse = test}}, \
+             book_info = {{page = test, parse = test}}, toc = {{page = test, parse = test}}, \
+             chapter = {{page = test, parse = test}}}}",
+            script
         );
+        assert!(crate::runtime::Runtime::new().load(&code, "test").is_err());
+        let schema = crate::runtime::Runtime::new()
+            .with_info_spec(spec)
+            .load(&code, "test")
+            .unwrap();
+        assert_eq!(schema.schema_info.description, "");
+    }
+
+    #[test]
+    fn test_request_from_lua() {
+        let lua = mlua::Lua::new();
+        lua.globals().set("null", lua.null()).unwrap();
+        let request = |code: &str| lua.load(code).eval::<HttpRequest>();
+        for code in [
+            "return {url = 'https://test.com', body = nil}",
+            "return {url = 'https://test.com'}",
+            "return {url = 'https://test.com', body = ''}",
+            "return {url = 'https://test.com', body = {}}",
+            "return {url = 'https://test.com', body = null, headers = null, method = null}",
+        ] {
+            let request = request(code).unwrap();
+            assert!(request.body.is_empty(), "{}", code);
+            assert!(request.headers.is_empty(), "{}", code);
+            assert_eq!(request.method.as_str(), "GET", "{}", code);
+        }
+        let request = request("return {url = 'https://test.com', body = 'a=1'}").unwrap();
+        assert_eq!(request.body, b"a=1");
+
+        // a request passed to Lua with its absent fields as `null` comes back unchanged
+        let original = HttpRequest {
+            url: "https://test.com".to_string(),
+            body: b"abc".to_vec(),
+            ..Default::default()
+        };
+        let value = original.clone().into_lua(&lua).unwrap();
+        let request = HttpRequest::from_lua(value, &lua).unwrap();
+        assert_eq!(request.body, original.body);
+        let value = HttpRequest::default().into_lua(&lua).unwrap();
+        assert!(HttpRequest::from_lua(value, &lua).unwrap().body.is_empty());
     }
 
     #[test]
@@ -387,8 +1924,9 @@ return {
 }
 "#;
         let lua = mlua::Lua::new();
-        let table = lua.load(script).eval::<Table>().unwrap();
-        let schema = Schema::load(script, table).unwrap();
+        let table = lua.load(script).eval().unwrap();
+        let schema = Schema::load(&lua, script, table).unwrap();
+        assert_eq!(schema.source(), script);
         assert_eq!(
             schema.schema_info.id,
             uuid::uuid!("198ca153-ccae-4f82-9218-9b6657796b57")
@@ -399,7 +1937,107 @@ return {
         assert_eq!(schema.schema_info.lh_version, "1.0");
         assert_eq!(
             schema.schema_info.legal_domains,
-            hashset!["test.com".to_string(), "test2.com".to_string()]
+            BTreeSet::from(["test.com".to_string(), "test2.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test.com
+
+local function test()
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}
+"#;
+        let lua = mlua::Lua::new();
+        let table = lua.load(script).eval().unwrap();
+        let schema = Schema::load(&lua, script, table).unwrap();
+        let table = lua.load(script).eval().unwrap();
+        let same = Schema::load(&lua, script, table).unwrap();
+        assert_eq!(schema.fingerprint(), same.fingerprint());
+        let edited_script = script.replace("--@description: test", "--@description: edited");
+        let table = lua.load(&edited_script).eval().unwrap();
+        let edited = Schema::load(&lua, &edited_script, table).unwrap();
+        assert_ne!(schema.fingerprint(), edited.fingerprint());
+    }
+
+    #[test]
+    fn test_descriptor() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: test2.com
+--@legal-domains: test.com
+
+local function test()
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    session = {page = test, parse = test, wrap = test},
+}"#;
+        let schema = crate::runtime::Runtime::new().load(script, "test").unwrap();
+        let descriptor = serde_json::to_value(schema.descriptor()).unwrap();
+        assert_eq!(
+            descriptor,
+            serde_json::json!({
+                "id": "198ca153-ccae-4f82-9218-9b6657796b57",
+                "name": "test_schema",
+                "author": "test_author",
+                "description": "test",
+                "lh_version": "1.0",
+                "legal_domains": ["test.com", "test2.com"],
+                "capabilities": ["session"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_audit_domains() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: www.example.com
+--@legal-domains: https://cdn.example.com
+--@legal-domains: unused.example.com
+
+local function test()
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#;
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(script, "test").unwrap();
+        let audit = schema.audit_domains(&[
+            "www.example.com".to_string(),
+            "cdn.example.com".to_string(),
+            "api.example.com".to_string(),
+        ]);
+        assert_eq!(
+            audit,
+            DomainAudit {
+                unused: vec!["unused.example.com".to_string()],
+                undeclared: vec!["api.example.com".to_string()],
+            }
         );
     }
 
@@ -448,99 +2086,1933 @@ return {
             .session
             .as_ref()
             .unwrap()
-            .parse("".to_string())
+            .parse("".to_string(), "")
+            .unwrap()
+            .into_content()
             .unwrap();
-        let command =
-            CommandWithSession::new(&schema.book_info, schema.session.as_ref(), Some(session));
+        let command = CommandWithSession::new(
+            &schema.book_info,
+            schema.session.as_ref(),
+            Some(session.clone()),
+        );
         let path = command.page("123", ()).unwrap();
         assert_eq!(path.url, "https://www.example.com?session=test");
         assert_eq!(path.headers.get("User-Agent"), Some(&"test".to_string()));
+
+        let request = schema
+            .resolve_request(CommandKind::BookInfo, "123", PageParams::default(), Some(session))
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.url, "https://www.example.com?session=test");
+        assert_eq!(request.headers.get("User-Agent"), Some(&"test".to_string()));
+        let request = schema
+            .resolve_request(CommandKind::Search, "keyword", PageParams::default(), None)
+            .unwrap();
+        assert!(request.is_none());
+
+        let seeded = schema.with_session(serde_json::json!("seeded")).unwrap();
+        let request = schema
+            .resolve_request(CommandKind::BookInfo, "123", PageParams::default(), Some(seeded))
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.url, "https://www.example.com?session=seeded");
     }
 
     #[tokio::test]
-    async fn test_search() {
-        let runtime = crate::runtime::Runtime::new();
-        let schema = runtime
-            .load(
-                r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+    async fn test_login() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(200).insert_header("X-Token", "token"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
 --@name: test_schema
 --@author: test_author
 --@description: test
 --@lh-version: 1.0
---@legal-domains: www.example.com
+--@legal-domains: localhost
 
-local function search(keyword, page, content)
-    if page == 1 then
-        return "https://www.example.com"
-    end
-end
-local function search_parse(content)
-    return function()
-        return {
-            id = "1",
-            title = "title",
-            author = "author",
-            cover = "cover",
-            last_update = "last_update",
-            status = "status",
-            intro = "intro",
-        }
-    end
+local function test()
 end
-local function book_info()
+local function book_info(id)
+    return "{base}/" .. id
 end
-local function chapter()
+local function login()
+    return {url = "{base}/login", method = "POST"}
 end
-local function toc()
+local function login_parse(content, headers)
+    return headers["x-token"]
 end
-local function session()
+local function wrap(request, session)
+    request.headers = {Authorization = session}
+    return request
 end
 return {
-    search = {page = search, parse = search_parse},
-    book_info = {page = book_info, parse = book_info},
-    chapter = {page = chapter, parse = chapter},
-    toc = {page = toc, parse = toc},
-    session = {page = session, parse = session, wrap = session},
-}"#,
-                "test",
-            )
-            .unwrap();
-        let http = HttpClient::new(
-            reqwest::Client::new(),
-            hashset!["www.example.com".to_string()],
-        );
-        let mut items = schema.search("keyword", &http, None);
-        let first = items
-            .next_page()
-            .await
-            .unwrap()
-            .unwrap()
-            .next()
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    session = {page = login, parse = login_parse, wrap = wrap},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let session = schema.login(&http).await.unwrap();
+        assert!(session.is_some());
+        let request = schema
+            .resolve_request(CommandKind::BookInfo, "123", PageParams::default(), session)
             .unwrap()
             .unwrap();
-        assert_eq!(first.id, "1");
+        assert_eq!(request.headers.get("Authorization"), Some(&"token".to_string()));
     }
 
     #[tokio::test]
-    async fn test_book_info() {
-        let runtime = crate::runtime::Runtime::new();
-        let schema = runtime
-            .load(
-                r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+    async fn test_requires_session() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(403))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
 --@name: test_schema
 --@author: test_author
 --@description: test
 --@lh-version: 1.0
---@legal-domains: www.example.com
+--@legal-domains: localhost
+--@requires-session: true
 
-local function search()
+local function test()
 end
-local function book_info(id)
-    return "https://www.example.com/" .. id
+local function page(id)
+    return "{base}/" .. id
 end
-local function book_info_parse(content)
+local function wrap(request, session)
+    request.headers = {Authorization = session}
+    return request
+end
+return {
+    search = {page = page, parse = test},
+    book_info = {page = page, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    session = {page = test, parse = test, wrap = wrap},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        assert!(schema.schema_info.requires_session);
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        assert!(matches!(
+            schema.book_info("123", &http, None, None).await,
+            Err(crate::Error::SessionRequired)
+        ));
+        assert!(matches!(
+            schema.search("abc", &http, None, None).next_page().await,
+            Err(crate::Error::SessionRequired)
+        ));
+        let session = schema.with_session(serde_json::json!("token")).unwrap();
+        let request = schema
+            .resolve_request(CommandKind::BookInfo, "123", PageParams::default(), Some(session))
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.headers["Authorization"], "token");
+
+        let invalid = script.replace("--@requires-session: true", "--@requires-session: yes");
+        assert!(crate::runtime::Runtime::new().load(&invalid, "test").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_extra_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .and(header("X-Token", "abc"))
+            .and(header("Accept-Language", "zh"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .expect(4)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function page(id)
+    return {url = "{base}/" .. id, headers = {["Accept-Language"] = "zh"}}
+end
+local function search_parse()
+    return test
+end
+return {
+    search = {page = page, parse = search_parse},
+    book_info = {page = page, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let extra_headers = Some(HashMap::from([
+            ("X-Token".to_string(), "abc".to_string()),
+            // the header set by the page wins
+            ("accept-language".to_string(), "en".to_string()),
+        ]));
+        let info = schema.book_info("123", &http, None, extra_headers.clone()).await.unwrap();
+        assert!(info.is_none());
+        let info = schema.book_info_with_toc("123", &http, None, extra_headers.clone()).await;
+        assert!(info.unwrap().is_none());
+        let results = schema.book_info_batch(&["123"], &http, None, extra_headers.clone(), 1).await;
+        assert!(matches!(results[0].1, Ok(None)));
+        let item = schema.search_first("123", &http, None, extra_headers).await.unwrap();
+        assert!(item.is_none());
+    }
+
+    #[test]
+    fn test_sign() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: www.example.com
+--@sign: hmac-sha256 sign q,page,ts
+
+local function test()
+end
+local function search(keyword, page)
+    return "https://www.example.com/search?q=" .. keyword .. "&page=" .. page .. "&ts=100"
+end
+return {
+    search = {page = search, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#;
+        let schema = crate::runtime::Runtime::new().load(script, "test").unwrap();
+        let params = || PageParams {
+            page: 1,
+            ..Default::default()
+        };
+        let result = schema.resolve_request(CommandKind::Search, "abc", params(), None);
+        assert!(matches!(
+            result,
+            Err(crate::Error::SchemaError(crate::SchemaError::InvalidRequest(_)))
+        ));
+
+        let schema = schema.with_signing_secret("secret");
+        let request = schema
+            .resolve_request(CommandKind::Search, "abc", params(), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            request.url,
+            "https://www.example.com/search?q=abc&page=1&ts=100\
+             &sign=2f89c6720d2dc21100884166ee0c0c1fec30fd6869a3d83932cdeb9dedc4f230"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: www.example.com
+
+local function test()
+end
+local function search_parse(content)
+    local ids = {"1", ""}
+    local index = 0
+    return function()
+        index = index + 1
+        if ids[index] then
+            return {
+                id = ids[index],
+                title = "title",
+                author = "author",
+                cover = "cover",
+                last_update = "last_update",
+                status = "status",
+                intro = "intro",
+            }
+        end
+    end
+end
+local function book_info_parse(content)
+    return {title = 1}
+end
+return {
+    search = {page = test, parse = search_parse},
+    book_info = {page = test, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#;
+        let schema = crate::runtime::Runtime::new().load(script, "test").unwrap();
+        let report = schema.validate(CommandKind::Search, "body");
+        assert_eq!(report.items, 2);
+        assert!(!report.is_valid());
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].item, Some(1));
+        assert_eq!(report.issues[0].field, Some("id"));
+
+        let report = schema.validate(CommandKind::BookInfo, "body");
+        assert_eq!(report.items, 0);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].field, None);
+    }
+
+    #[tokio::test]
+    async fn test_warmup() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Set-Cookie", "sid=1; Path=/"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .and(header("Cookie", "sid=1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("title"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+--@warmup-url: {base}/
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/book"
+end
+local function book_info_parse(content)
+    return {
+        title = content,
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let client = reqwest::Client::builder().cookie_store(true).build().unwrap();
+        let http = HttpClient::new(client, hashset!["localhost".to_string()]);
+        for _ in 0..2 {
+            let info = schema.book_info("1", &http, None, None).await.unwrap().unwrap();
+            assert_eq!(info.title, "title");
+        }
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests[0].url.path(), "/");
+    }
+
+    #[tokio::test]
+    async fn test_login_with_steps() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("csrf=abc"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .and(header("X-CSRF-Token", "abc"))
+            .respond_with(ResponseTemplate::new(200).insert_header("X-Token", "token"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function login()
+    return "{base}/login"
+end
+local function post_credentials(content, headers)
+    local csrf = string.match(content, "csrf=(%w+)")
+    return {
+        url = "{base}/login",
+        method = "POST",
+        headers = {["X-CSRF-Token"] = csrf},
+    }
+end
+local function login_parse(content, headers)
+    return headers["x-token"]
+end
+local function wrap(request, session)
+    return request
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    session = {page = login, parse = login_parse, wrap = wrap, steps = {post_credentials}},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let session = schema.login_with_steps(&http).await.unwrap().unwrap();
+        assert_eq!(session.as_str().as_deref(), Some("token"));
+    }
+
+    #[test]
+    fn test_none_as_null() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: www.example.com
+
+local function test()
+end
+local function book_info(id)
+    return "https://www.example.com/" .. id
+end
+local function wrap(request, session)
+    if request.json == nil then
+        request.headers = {json = "nil"}
+    else
+        request.headers = {json = "null"}
+    end
+    request.json = nil
+    return request
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    session = {page = test, parse = test, wrap = wrap},
+}"#;
+        let runtime = crate::runtime::Runtime::new();
+        let json_header = |schema: &Schema| {
+            let session = schema.with_session(serde_json::json!("token")).unwrap();
+            let request = schema
+                .resolve_request(CommandKind::BookInfo, "1", PageParams::default(), Some(session))
+                .unwrap()
+                .unwrap();
+            request.headers["json"].clone()
+        };
+        let schema = runtime.load(script, "test").unwrap();
+        assert_eq!(json_header(&schema), "null");
+        let schema = runtime.load(script, "test").unwrap().with_none_as_null(false);
+        assert_eq!(json_header(&schema), "nil");
+    }
+
+    #[test]
+    fn test_id_pattern() {
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: www.example.com
+--@id-pattern: (?:/book/|book-)?(\d+)$
+
+local function test()
+end
+local function book_info(id)
+    return "https://www.example.com/" .. id
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#;
+        let schema = crate::runtime::Runtime::new().load(script, "test").unwrap();
+        for id in ["/book/123", "book-123", "123"] {
+            assert_eq!(schema.schema_info.canonical_id(id), "123");
+            let request = schema
+                .resolve_request(CommandKind::BookInfo, id, PageParams::default(), None)
+                .unwrap()
+                .unwrap();
+            assert_eq!(request.url, "https://www.example.com/123");
+        }
+        assert_eq!(schema.schema_info.canonical_id("abc"), "abc");
+
+        assert_eq!(schema.book_url("/book/123"), None);
+
+        let with_template = script.replace(
+            "--@lh-version: 1.0",
+            "--@lh-version: 1.0\n--@book-url-template: https://www.example.com/book/{id}",
+        );
+        let schema = crate::runtime::Runtime::new().load(&with_template, "test").unwrap();
+        assert_eq!(
+            schema.book_url("/book/123").as_deref(),
+            Some("https://www.example.com/book/123")
+        );
+        let without_id = script.replace(
+            "--@lh-version: 1.0",
+            "--@lh-version: 1.0\n--@book-url-template: https://www.example.com/book/",
+        );
+        assert!(crate::runtime::Runtime::new().load(&without_id, "test").is_err());
+
+        let script = script.replace("--@id-pattern: (?:/book/|book-)?(\\d+)$\n", "");
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        assert!(schema.schema_info.id_pattern.is_none());
+        assert_eq!(schema.schema_info.canonical_id("/book/123"), "/book/123");
+
+        let script = script.replace(
+            "--@lh-version: 1.0",
+            "--@lh-version: 1.0\n--@id-pattern: \\d+",
+        );
+        assert!(matches!(
+            crate::runtime::Runtime::new().load(&script, "test"),
+            Err(crate::Error::ScriptParseError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime
+            .load(
+                r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: www.example.com
+
+local function search(keyword, page, content)
+    if page == 1 then
+        return "https://www.example.com"
+    end
+end
+local function search_parse(content)
+    return function()
+        return {
+            id = "1",
+            title = "title",
+            author = "author",
+            cover = "cover",
+            last_update = "last_update",
+            status = "status",
+            intro = "intro",
+        }
+    end
+end
+local function book_info()
+end
+local function chapter()
+end
+local function toc()
+end
+local function session()
+end
+return {
+    search = {page = search, parse = search_parse},
+    book_info = {page = book_info, parse = book_info},
+    chapter = {page = chapter, parse = chapter},
+    toc = {page = toc, parse = toc},
+    session = {page = session, parse = session, wrap = session},
+}"#,
+                "test",
+            )
+            .unwrap();
+        let http = HttpClient::new(
+            reqwest::Client::new(),
+            hashset!["www.example.com".to_string()],
+        );
+        let mut items = schema.search("keyword", &http, None, None);
+        let first = items
+            .next_page()
+            .await
+            .unwrap()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.id, "1");
+    }
+
+    #[cfg(feature = "pkg-json")]
+    #[tokio::test]
+    async fn test_search_json_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/search"))
+            .and(header("content-type", "application/json"))
+            .respond_with(|request: &wiremock::Request| {
+                ResponseTemplate::new(200).set_body_bytes(request.body.clone())
+            })
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local json = require("@json")
+local function search(keyword, page)
+    if page == 1 then
+        return {url = "{base}/search", method = "POST", json = {q = keyword, page = page}}
+    end
+end
+local function search_parse(content)
+    local body = json.decode(content)
+    local done = false
+    return function()
+        if not done then
+            done = true
+            return {
+                id = tostring(body.page),
+                title = body.q,
+                author = "author",
+                cover = "cover",
+                last_update = "last_update",
+                status = "status",
+                intro = "intro",
+            }
+        end
+    end
+end
+local function test()
+end
+return {
+    search = {page = search, parse = search_parse},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let item = schema.search_first("keyword", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(item.id, "1");
+        assert_eq!(item.title, "keyword");
+    }
+
+    #[tokio::test]
+    async fn test_charset() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book/123"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(b"\xC4\xE3\xBA\xC3".to_vec(), "text/html"),
+            )
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+--@charset: gbk
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/book/" .. id
+end
+local function book_info_parse(content)
+    return {
+        title = content,
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        assert_eq!(schema.schema_info.charset.as_deref(), Some("gbk"));
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_charset(schema.schema_info.encoding().unwrap());
+        let info = schema.book_info("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.title, "你好");
+
+        let result = SchemaInfo::from_str(&script.replace("gbk", "not-a-charset"));
+        assert!(matches!(result, Err(crate::Error::ScriptParseError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_search_first() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/search"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function search(keyword, page)
+    return "{base}/search"
+end
+local function search_parse(content)
+    return function()
+        return {
+            id = content,
+            title = "title",
+            author = "author",
+            cover = "cover",
+            last_update = "last_update",
+            status = "status",
+            intro = "intro",
+        }
+    end
+end
+return {
+    search = {page = search, parse = search_parse},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let item = schema.search_first("keyword", &http, None, None).await.unwrap();
+        assert_eq!(item.unwrap().id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_book_info() {
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime
+            .load(
+                r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: www.example.com
+
+local function search()
+end
+local function book_info(id)
+    return "https://www.example.com/" .. id
+end
+local function book_info_parse(content)
+    return {
+        title = "title",
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+end
+local function chapter()
+end
+local function toc()
+end
+local function session()
+end
+return {
+    search = {page = search, parse = search},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = chapter, parse = chapter},
+    toc = {page = toc, parse = toc},
+    session = {page = session, parse = session, wrap = session},
+}"#,
+                "test",
+            )
+            .unwrap();
+        let http = HttpClient::new(
+            reqwest::Client::new(),
+            hashset!["www.example.com".to_string()],
+        );
+        let info = schema.book_info("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.title, "title");
+        assert_eq!(info.author, "author");
+        assert_eq!(info.cover.full, "cover");
+        assert_eq!(info.last_update, "last_update");
+        assert_eq!(info.status, "status");
+        assert_eq!(info.intro, "intro");
+    }
+
+    #[tokio::test]
+    async fn test_book_info_chapter_count() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("42"))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    return {
+        title = "title",
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+        chapter_count = tonumber(content),
+    }
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let info = schema.book_info("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.chapter_count, Some(42));
+    }
+
+    #[cfg(feature = "pkg-json")]
+    #[tokio::test]
+    async fn test_preprocess() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"callback({"title": "title", "author": "author", "intro": "intro"});"#,
+            ))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local json = require("@json")
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    local info = json.decode(content)
+    info.cover = ""
+    info.last_update = ""
+    info.status = ""
+    return info
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    preprocess = function(body)
+        return string.match(body, "^%s*[%w_]+%((.*)%)%s*;?%s*$") or body
+    end,
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let info = schema.book_info("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.title, "title");
+        let info = schema.book_info_with_toc("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.info.intro, "intro");
+    }
+
+    #[tokio::test]
+    async fn test_parse_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("page"))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    while true do
+    end
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        assert!(schema.lua.app_data_ref::<ParseDeadlines>().is_none());
+        let schema = schema.with_parse_timeout(Duration::from_millis(100));
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let result = schema.book_info("123", &http, None, None).await;
+        assert!(matches!(result, Err(crate::Error::ResourceLimit(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_parse_timeout_concurrent() {
+        let server = MockServer::start().await;
+        for (id, body) in [("slow", "0.3"), ("stuck", "")] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    local seconds = tonumber(content)
+    local start = os.clock()
+    while not seconds or os.clock() - start < seconds do
+    end
+    return {title = "title", author = "author", cover = "cover", last_update = "",
+        status = "", intro = "intro"}
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let load = |timeout| {
+            Arc::new(runtime.load(&script, "test").unwrap().with_parse_timeout(timeout))
+        };
+        let (patient, hasty) = (load(Duration::from_secs(10)), load(Duration::from_millis(100)));
+        let http = Arc::new(HttpClient::new(
+            reqwest::Client::new(),
+            hashset!["localhost".to_string()],
+        ));
+        // the parses share the Lua, yet each one is stopped by its own deadline only
+        let slow = tokio::spawn({
+            let http = http.clone();
+            async move { patient.book_info("slow", &http, None, None).await }
+        });
+        let stuck = tokio::spawn(async move { hasty.book_info("stuck", &http, None, None).await });
+        assert!(matches!(stuck.await.unwrap(), Err(crate::Error::ResourceLimit(_))));
+        assert_eq!(slow.await.unwrap().unwrap().unwrap().intro, "intro");
+    }
+
+    #[tokio::test]
+    async fn test_parse_final_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/b/1"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/book/slug-1"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/book/slug-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/b/" .. id
+end
+local function book_info_parse(content, final_url)
+    return {
+        title = string.match(final_url, "/book/([^/]+)$"),
+        author = "author",
+        cover = "cover",
+        last_update = "",
+        status = "",
+        intro = content,
+    }
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let info = schema.book_info("1", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.title, "slug-1");
+        assert_eq!(info.intro, "book");
+    }
+
+    #[tokio::test]
+    async fn test_book_info_wrong_type() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("page"))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    return "not a table"
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        match schema.book_info("123", &http, None, None).await {
+            Err(crate::Error::ScriptParseError(message)) => {
+                assert!(message.starts_with("book_info parse returned a string value"));
+            }
+            other => panic!(
+                "unexpected result: {:?}",
+                other.map(|info| info.map(|info| info.title))
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_book_info_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("gone"))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    if content == "gone" then
+        return nil
+    end
+    error("unexpected page")
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        assert!(schema.book_info("123", &http, None, None).await.unwrap().is_none());
+        assert!(
+            schema
+                .book_info_with_toc("123", &http, None, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(!schema.validate(CommandKind::BookInfo, "gone").is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_self_test() {
+        let server = MockServer::start().await;
+        for id in ["1", "2"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!("title {}", id)))
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    return {
+        title = content,
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+    tests = {
+        book_info = {
+            {id = "1", title = "title 1"},
+            {id = "2", title = "title 1"},
+        },
+    },
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let report = schema.self_test(&http).await.unwrap();
+        assert_eq!(report.cases.len(), 2);
+        assert!(!report.passed());
+        assert!(report.cases[0].failure.is_none());
+        let failures: Vec<_> = report.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].command, CommandKind::BookInfo);
+        assert_eq!(failures[0].input, "2");
+        assert_eq!(
+            failures[0].failure.as_deref(),
+            Some(r#"expected title "title 1", got "title 2""#)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_book_info_batch() {
+        let server = MockServer::start().await;
+        for id in ["1", "2"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", id)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(format!("title {}", id))
+                        .set_delay(Duration::from_secs(1)),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    if content == "" then
+        error("book not found")
+    end
+    return {
+        title = content,
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+
+        let start = Instant::now();
+        let mut results = schema
+            .book_info_batch(&["1", "2", "missing"], &http, None, None, 3)
+            .await;
+        // one after the other, the two delayed requests take at least 2s
+        assert!(start.elapsed() < Duration::from_secs(2));
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(results.len(), 3);
+        let title = |index: usize| {
+            let info = results[index].1.as_ref().unwrap().as_ref().unwrap();
+            info.title.clone()
+        };
+        assert_eq!(title(0), "title 1");
+        assert_eq!(title(1), "title 2");
+        assert_eq!(results[2].0, "missing");
+        assert!(results[2].1.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_book_info_with_toc() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("book"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function search()
+end
+local function book_info(id)
+    return "{base}/" .. id
+end
+local function book_info_parse(content)
+    local info = {
+        title = "title",
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+    local index = 0
+    local toc = function()
+        index = index + 1
+        if index <= 2 then
+            return {
+                id = tostring(index),
+                title = "chapter " .. index,
+                tags = {" Fantasy ", "fantasy", "Action"},
+            }
+        end
+    end
+    return info, toc
+end
+local function chapter()
+end
+local function toc()
+end
+return {
+    search = {page = search, parse = search},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = chapter, parse = chapter},
+    toc = {page = toc, parse = toc},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new()
+            .load(&script, "test")
+            .unwrap()
+            .with_tag_normalization(TagNormalization {
+                lowercase: true,
+                max_tags: 8,
+            });
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let info = schema.book_info_with_toc("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.info.title, "title");
+        let toc = info.toc.unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].id, "1");
+        assert_eq!(toc[1].title, "chapter 2");
+        assert_eq!(toc[1].tags, vec!["fantasy", "action"]);
+
+        let info = schema.book_info("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.title, "title");
+    }
+
+    #[tokio::test]
+    async fn test_refetch() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("/api/123"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("title from api"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/book/" .. id
+end
+local function book_info_parse(content)
+    if string.sub(content, 1, 5) == "/api/" then
+        return {__refetch = {url = "{base}" .. content}}
+    end
+    return {
+        title = content,
+        author = "author",
+        cover = "cover",
+        last_update = "last_update",
+        status = "status",
+        intro = "intro",
+    }
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let info = schema.book_info("123", &http, None, None).await.unwrap().unwrap();
+        assert_eq!(info.title, "title from api");
+    }
+
+    #[tokio::test]
+    async fn test_toc_cursor() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .and(query_param("cursor", "c2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("2"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function toc(id, page, content, cursor)
+    if cursor then
+        return "{base}/toc?cursor=" .. cursor
+    end
+    return "{base}/toc"
+end
+local function toc_parse(content)
+    local done = false
+    local iter = function()
+        if not done then
+            done = true
+            return {id = content, title = "chapter " .. content}
+        end
+    end
+    if content == "1" then
+        return iter, {next_cursor = "c2", total = 2}
+    end
+    return iter
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.toc("123", &http, None, None);
+        assert_eq!(items.estimated_total(), None);
+        assert_eq!(items.last_meta(), None);
+        let first = items.next_page().await.unwrap().unwrap().next().unwrap().unwrap();
+        assert_eq!(first.id, "1");
+        assert_eq!(items.estimated_total(), Some(2));
+        let meta = PageMeta {
+            page: 1,
+            has_next: true,
+            total: Some(2),
+        };
+        assert_eq!(items.last_meta(), Some(&meta));
+        let (mut page, meta) = items.next_page_with_meta().await.unwrap().unwrap();
+        assert_eq!(page.next().unwrap().unwrap().id, "2");
+        assert_eq!(
+            meta,
+            PageMeta {
+                page: 2,
+                has_next: false,
+                total: Some(2),
+            }
+        );
+        assert!(items.next_page().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_for_each_item() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .and(query_param("page", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .and(query_param("page", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("2"))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function toc(id, page)
+    return "{base}/toc?page=" .. page
+end
+local function toc_parse(content)
+    local index = 0
+    return function()
+        index = index + 1
+        if index <= 2 then
+            return {id = content .. "-" .. index, title = "chapter " .. index}
+        end
+    end
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut ids = Vec::new();
+        schema
+            .toc("123", &http, None, None)
+            .for_each_item(|item| {
+                ids.push(item.unwrap().id);
+                if ids.len() == 2 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["1-1", "1-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_parallel() {
+        let server = MockServer::start().await;
+        for page in 1..=3 {
+            let delay = if page == 1 { 0 } else { 1000 };
+            Mock::given(method("GET"))
+                .and(path("/toc"))
+                .and(query_param("page", page.to_string()))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(page.to_string())
+                        .set_delay(Duration::from_millis(delay)),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function toc(id, page)
+    return "{base}/toc?page=" .. page
+end
+local function toc_parse(content)
+    local index = 0
+    local iter = function()
+        index = index + 1
+        if index <= 2 then
+            return {id = content .. "-" .. index, title = "chapter " .. index}
+        end
+    end
+    return iter, {total_pages = 3}
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.toc("123", &http, None, None);
+        let start = Instant::now();
+        let ids: Vec<_> = items
+            .fetch_all_parallel(2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.id)
+            .collect();
+        // one after the other, the two delayed pages take at least 2s
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(ids, ["1-1", "1-2", "2-1", "2-2", "3-1", "3-2"]);
+        assert!(items.next_page().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_parallel_retry() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .and(query_param("page", "3"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        for (page, times) in [(1, 3), (2, 2), (3, 1)] {
+            Mock::given(method("GET"))
+                .and(path("/toc"))
+                .and(query_param("page", page.to_string()))
+                .respond_with(ResponseTemplate::new(200).set_body_string(page.to_string()))
+                .expect(times)
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local last_page_calls = 0
+local function toc(id, page)
+    if page == 3 then
+        last_page_calls = last_page_calls + 1
+        if last_page_calls == 1 then
+            return nil
+        end
+    end
+    return "{base}/toc?page=" .. page
+end
+local function toc_parse(content)
+    local done = false
+    return function()
+        if not done then
+            done = true
+            return {id = content, title = "chapter " .. content}
+        end
+    end, {total_pages = 3}
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.toc("123", &http, None, None);
+        // a page within `total_pages` without a request is reported rather than skipped
+        assert!(matches!(
+            items.fetch_all_parallel(2).await,
+            Err(crate::Error::ScriptParseError(message)) if message.contains("page 3")
+        ));
+        assert_eq!(items.to_cursor().page, 1);
+        // a failed page leaves the progress as it was, so the call can be retried
+        assert!(matches!(
+            items.fetch_all_parallel(2).await,
+            Err(crate::Error::SchemaError(crate::SchemaError::UnexpectedStatus(500)))
+        ));
+        let cursor = items.to_cursor();
+        assert_eq!((cursor.page, cursor.finished), (1, false));
+        let ids: Vec<_> = items
+            .fetch_all_parallel(2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.id)
+            .collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+        assert!(items.next_page().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_toc_resume() {
+        let server = MockServer::start().await;
+        for page in 0..=2 {
+            Mock::given(method("GET"))
+                .and(path(format!("/toc/{}", page)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(page.to_string()))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+--@page-base: 0
+
+local function test()
+end
+local function toc(id, page, content)
+    if page > 2 then
+        return nil
+    end
+    if page > 0 then
+        assert(content == tostring(page - 1))
+    end
+    return "{base}/toc/" .. page
+end
+local function toc_parse(content)
+    local done = false
+    local iter = function()
+        if not done then
+            done = true
+            return {id = content, title = "chapter " .. content}
+        end
+    end
+    if content == "0" then
+        return iter, {total = 3}
+    end
+    return iter
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.toc("123", &http, None, None);
+        let first = items.next_page().await.unwrap().unwrap().next().unwrap().unwrap();
+        assert_eq!(first.id, "0");
+        let cursor = items.to_cursor();
+        assert_eq!((cursor.page, cursor.page_base), (1, 0));
+        drop(items);
+
+        let saved = serde_json::to_string(&cursor).unwrap();
+        let cursor: PageCursor = serde_json::from_str(&saved).unwrap();
+        let mut items = schema.resume_toc(cursor, "123", &http, None, None);
+        assert_eq!(items.estimated_total(), Some(3));
+        let mut ids = Vec::new();
+        while let Some(page) = items.next_page().await.unwrap() {
+            for item in page {
+                ids.push(item.unwrap().id);
+            }
+        }
+        assert_eq!(ids, vec!["1", "2"]);
+        // the resumed pages are not taken for the first one, which reported the total
+        assert_eq!(items.estimated_total(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_page_base() {
+        let server = MockServer::start().await;
+        for page in 0..=1 {
+            Mock::given(method("GET"))
+                .and(path("/toc"))
+                .and(query_param("page", page.to_string()))
+                .respond_with(ResponseTemplate::new(200).set_body_string(page.to_string()))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+--@page-base: 0
+
+local function test()
+end
+local function toc(id, page)
+    if page > 1 then
+        return nil
+    end
+    return "{base}/toc?page=" .. page
+end
+local function toc_parse(content)
+    local done = false
+    return function()
+        if not done then
+            done = true
+            return {id = content, title = "chapter " .. content}
+        end
+    end
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        assert_eq!(schema.schema_info.page_base, 0);
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.toc("123", &http, None, None);
+        let (page, meta) = items.next_page_with_meta().await.unwrap().unwrap();
+        assert_eq!(meta.page, 0);
+        let mut ids: Vec<_> = page.map(|item| item.unwrap().id).collect();
+        while let Some(page) = items.next_page().await.unwrap() {
+            ids.extend(page.map(|item| item.unwrap().id));
+        }
+        assert_eq!(ids, vec!["0", "1"]);
+
+        let invalid = script.replace("--@page-base: 0", "--@page-base: first");
+        assert!(runtime.load(&invalid, "test").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_toc_next_page_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/toc/next"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("2"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function toc(id, page)
+    if page == 1 then
+        return "{base}/toc"
+    end
+    return "{base}/toc/page" .. page
+end
+local function toc_parse(content)
+    local done = false
+    local iter = function()
+        if not done then
+            done = true
+            return {id = content, title = "chapter " .. content}
+        end
+    end
+    if content == "1" then
+        return iter, {next_page_url = "{base}/toc/next"}
+    end
+    return iter
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.toc("123", &http, None, None);
+        let first = items.next_page().await.unwrap().unwrap().next().unwrap().unwrap();
+        assert_eq!(first.id, "1");
+        let second = items.next_page().await.unwrap().unwrap().next().unwrap().unwrap();
+        assert_eq!(second.id, "2");
+        assert!(items.next_page().await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "debug")]
+    #[tokio::test]
+    async fn test_next_page_debug() {
+        let server = MockServer::start().await;
+        let body = "x".repeat(DEBUG_EXCERPT_CHARS + 10);
+        Mock::given(method("GET"))
+            .and(path("/search"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body.clone()))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function search(keyword, page)
+    if page == 1 then
+        return "{base}/search"
+    end
+end
+local function search_parse(content)
+    return function() end
+end
+return {
+    search = {page = search, parse = search_parse},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.search("keyword", &http, None, None);
+        let (mut page, debug) = items.next_page_debug().await.unwrap().unwrap();
+        assert!(page.next().is_none());
+        assert_eq!(debug.url, mock_url(&server, "/search"));
+        assert_eq!(debug.body_len, body.len());
+        assert_eq!(debug.excerpt, body[..DEBUG_EXCERPT_CHARS]);
+        assert!(items.next_page_debug().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrency() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/book"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("book")
+                    .set_delay(Duration::from_millis(200)),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+--@max-concurrency: 1
+
+local function test()
+end
+local function book_info(id)
+    return "{base}/book?id=" .. id
+end
+local function book_info_parse(content)
     return {
-        title = "title",
+        title = content,
         author = "author",
         cover = "cover",
         last_update = "last_update",
@@ -548,33 +4020,226 @@ local function book_info_parse(content)
         intro = "intro",
     }
 end
-local function chapter()
+return {
+    search = {page = test, parse = test},
+    book_info = {page = book_info, parse = book_info_parse},
+    chapter = {page = test, parse = test},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let runtime = crate::runtime::Runtime::new();
+        let schema = runtime.load(&script, "test").unwrap();
+        assert_eq!(schema.schema_info.max_concurrency, Some(1));
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let start = Instant::now();
+        let results = schema.book_info_batch(&["1", "2"], &http, None, None, 2).await;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert!(results.iter().all(|(_, info)| info.is_ok()));
+
+        let invalid = script.replace("--@max-concurrency: 1", "--@max-concurrency: 0");
+        assert!(matches!(
+            runtime.load(&invalid, "test"),
+            Err(crate::Error::ScriptParseError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_next_page_dropped() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/toc/1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("1")
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .mount(&server)
+            .await;
+        let script = COLLECT_SCRIPT.replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let budget = crate::http::ByteBudget::new(100);
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()])
+            .with_budget(budget.clone());
+        let mut items = schema.toc("123", &http, None, None);
+        let pending = tokio::time::timeout(Duration::from_millis(50), items.next_page()).await;
+        assert!(pending.is_err());
+        drop(items);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        // the body was never read, so nothing went on with the request after the drop
+        assert_eq!(budget.remaining(), 100);
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+
+        let mut items = schema.toc("123", &http, None, None);
+        assert!(items.next_page().await.unwrap().is_some());
+        assert_eq!(budget.remaining(), 99);
+    }
+
+    #[tokio::test]
+    async fn test_next_page_dropped_resume() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .and(query_param("cursor", "c2"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("2")
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .and(query_param("cursor", "c2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("2"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/toc"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
 end
-local function toc()
+local function toc(id, page, content, cursor)
+    if cursor then
+        return "{base}/toc?cursor=" .. cursor
+    end
+    return "{base}/toc"
 end
-local function session()
+local function toc_parse(content)
+    local done = false
+    local iter = function()
+        if not done then
+            done = true
+            return {id = content, title = "chapter " .. content}
+        end
+    end
+    if content == "1" then
+        return iter, {next_cursor = "c2"}
+    end
+    return iter
 end
 return {
-    search = {page = search, parse = search},
-    book_info = {page = book_info, parse = book_info_parse},
-    chapter = {page = chapter, parse = chapter},
-    toc = {page = toc, parse = toc},
-    session = {page = session, parse = session, wrap = session},
-}"#,
-                "test",
-            )
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.toc("123", &http, None, None);
+        let mut page = items.next_page().await.unwrap().unwrap();
+        assert_eq!(page.next().unwrap().unwrap().id, "1");
+        let pending = tokio::time::timeout(Duration::from_millis(50), items.next_page()).await;
+        assert!(pending.is_err());
+        // the dropped fetch kept the cursor, so the same page is requested again
+        let mut page = items.next_page().await.unwrap().unwrap();
+        assert_eq!(page.next().unwrap().unwrap().id, "2");
+        assert!(items.next_page().await.unwrap().is_none());
+    }
+
+    const COLLECT_SCRIPT: &str = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function toc(id, page)
+    if page <= 2 then
+        return "{base}/toc/" .. page
+    end
+end
+local function toc_parse(content)
+    local index = 0
+    return function()
+        index = index + 1
+        if index <= 2 then
+            return {id = content .. "-" .. index, title = "title"}
+        end
+    end
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = test, parse = test},
+    toc = {page = toc, parse = toc_parse},
+}"#;
+
+    async fn collect_server(delay: Duration) -> MockServer {
+        let server = MockServer::start().await;
+        for page in ["1", "2"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/toc/{}", page)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(page)
+                        .set_delay(delay),
+                )
+                .mount(&server)
+                .await;
+        }
+        server
+    }
+
+    #[tokio::test]
+    async fn test_collect_all() {
+        let server = collect_server(Duration::ZERO).await;
+        let script = COLLECT_SCRIPT.replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+
+        let items = schema
+            .toc("123", &http, None, None)
+            .collect_all(100, Duration::from_secs(10))
+            .await
             .unwrap();
-        let http = HttpClient::new(
-            reqwest::Client::new(),
-            hashset!["www.example.com".to_string()],
-        );
-        let info = schema.book_info("123", &http, None).await.unwrap();
-        assert_eq!(info.title, "title");
-        assert_eq!(info.author, "author");
-        assert_eq!(info.cover, "cover");
-        assert_eq!(info.last_update, "last_update");
-        assert_eq!(info.status, "status");
-        assert_eq!(info.intro, "intro");
+        let ids: Vec<_> = items.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(ids, ["1-1", "1-2", "2-1", "2-2"]);
+
+        let items = schema
+            .toc("123", &http, None, None)
+            .collect_all(3, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 3);
+
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["example.com".to_string()]);
+        let result = schema
+            .toc("123", &http, None, None)
+            .collect_all(100, Duration::from_secs(10))
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::Error::SchemaError(crate::SchemaError::NotAllowedDomain(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_collect_all_timeout() {
+        let server = collect_server(Duration::from_millis(300)).await;
+        let script = COLLECT_SCRIPT.replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+
+        let items = schema
+            .toc("123", &http, None, None)
+            .collect_all(100, Duration::from_millis(450))
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 2);
     }
 
     #[tokio::test]
@@ -622,7 +4287,7 @@ return {
             reqwest::Client::new(),
             hashset!["www.example.com".to_string()],
         );
-        let mut items = schema.chapter("123", &http, None);
+        let mut items = schema.chapter("123", &http, None, None);
         let first = items
             .next_page()
             .await
@@ -634,6 +4299,101 @@ return {
         assert!(matches!(first, Paragraph::Text(content) if content == "test"));
     }
 
+    #[tokio::test]
+    async fn test_chapter_text() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("chapter text"))
+            .mount(&server)
+            .await;
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function chapter(id, page)
+    if page == 1 then
+        return "{base}/" .. id
+    end
+end
+local function chapter_parse(content)
+    return content
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = chapter, parse = chapter_parse},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let mut items = schema.chapter("123", &http, None, None);
+        let paragraphs: Vec<_> = items
+            .next_page()
+            .await
+            .unwrap()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(paragraphs.len(), 1);
+        assert!(matches!(&paragraphs[0], Paragraph::Text(text) if text == "chapter text"));
+    }
+
+    #[tokio::test]
+    async fn test_chapter_content() {
+        let server = MockServer::start().await;
+        for part in ["1", "2"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/123/{}", part)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!("part {}", part)))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let script = r#"--@id: 198ca153-ccae-4f82-9218-9b6657796b57
+--@name: test_schema
+--@author: test_author
+--@description: test
+--@lh-version: 1.0
+--@legal-domains: localhost
+
+local function test()
+end
+local function chapter(id, part)
+    if part <= 2 then
+        return "{base}/" .. id .. "/" .. part
+    end
+end
+local function chapter_parse(content)
+    return content
+end
+return {
+    search = {page = test, parse = test},
+    book_info = {page = test, parse = test},
+    chapter = {page = chapter, parse = chapter_parse},
+    toc = {page = test, parse = test},
+}"#
+        .replace("{base}", &mock_url(&server, ""));
+        let schema = crate::runtime::Runtime::new().load(&script, "test").unwrap();
+        let http = HttpClient::new(reqwest::Client::new(), hashset!["localhost".to_string()]);
+        let content = schema.chapter_content("123", &http, None).await.unwrap();
+        let texts: Vec<_> = content
+            .paragraphs
+            .iter()
+            .map(|paragraph| match paragraph {
+                Paragraph::Text(text) => text.as_str(),
+                paragraph => panic!("unexpected paragraph: {:?}", paragraph),
+            })
+            .collect();
+        assert_eq!(texts, ["part 1", "part 2"]);
+    }
+
     #[tokio::test]
     async fn test_toc() {
         let runtime = crate::runtime::Runtime::new();
@@ -679,7 +4439,7 @@ return {
             reqwest::Client::new(),
             hashset!["www.example.com".to_string()],
         );
-        let mut items = schema.toc("123", &http, None);
+        let mut items = schema.toc("123", &http, None, None);
         let first = items
             .next_page()
             .await
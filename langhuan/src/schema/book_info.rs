@@ -1,7 +1,9 @@
 use mlua::{FromLua, Function, LuaSerdeExt};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use super::{Command, HttpRequest};
+use super::{
+    Command, CommandKind, HttpRequest, Parsed, TagNormalization, TocItemIter, normalize_text,
+};
 
 use crate::Result;
 
@@ -9,16 +11,64 @@ use crate::Result;
 pub struct BookInfoCommand {
     page: Function,
     parse: Function,
+    pub(super) normalize_text: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct BookInfo {
     pub title: String,
     pub author: String,
-    pub cover: String,
+    pub cover: Cover,
     pub last_update: String,
     pub status: String,
     pub intro: String,
+    /// the number of chapters, if the book page reports it
+    #[serde(default)]
+    pub chapter_count: Option<u64>,
+}
+
+/// The cover of a book, returned by `parse` as a url or as a table `{ thumb, full }`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "CoverRepr")]
+pub struct Cover {
+    /// a smaller image for lists, if the site has one
+    pub thumb: Option<String>,
+    pub full: String,
+}
+
+impl Cover {
+    /// The thumbnail, or the full image if there is none.
+    pub fn thumb_or_full(&self) -> &str {
+        self.thumb.as_deref().unwrap_or(&self.full)
+    }
+}
+
+impl From<String> for Cover {
+    fn from(full: String) -> Self {
+        Cover { thumb: None, full }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CoverRepr {
+    Full(String),
+    Sizes { thumb: Option<String>, full: String },
+}
+
+impl From<CoverRepr> for Cover {
+    fn from(repr: CoverRepr) -> Self {
+        match repr {
+            CoverRepr::Full(full) => full.into(),
+            CoverRepr::Sizes { thumb, full } => Cover { thumb, full },
+        }
+    }
+}
+
+/// The book info together with the table of contents embedded in the same page.
+pub struct BookInfoWithToc {
+    pub info: BookInfo,
+    pub toc: Option<TocItemIter>,
 }
 
 impl FromLua for BookInfo {
@@ -32,7 +82,42 @@ impl FromLua for BookInfoCommand {
         let table: mlua::Table = lua.unpack(value)?;
         let page = table.get("page")?;
         let parse = table.get("parse")?;
-        Ok(BookInfoCommand { page, parse })
+        Ok(BookInfoCommand {
+            page,
+            parse,
+            normalize_text: false,
+        })
+    }
+}
+
+impl BookInfoCommand {
+    fn normalize(&self, mut info: BookInfo) -> BookInfo {
+        if self.normalize_text {
+            info.intro = normalize_text(&info.intro);
+        }
+        info
+    }
+
+    /// Parse the page, also taking the toc iterator if `parse` returns one as a second value.
+    /// The tags of its items are normalized like those of the toc command.
+    ///
+    /// `None` if `parse` returns `nil` for a book that does not exist.
+    pub fn parse_with_toc(
+        &self,
+        content: String,
+        final_url: &str,
+        tag_normalization: Option<TagNormalization>,
+    ) -> Result<Parsed<Option<BookInfoWithToc>>> {
+        let (info, toc): (Parsed<Option<BookInfo>>, Option<Function>) = self
+            .parse
+            .call((content, final_url))
+            .map_err(|e| CommandKind::BookInfo.parse_error(e))?;
+        Ok(info.map(|info| {
+            info.map(|info| BookInfoWithToc {
+                info: self.normalize(info),
+                toc: toc.map(|toc| TocItemIter::new(toc, tag_normalization)),
+            })
+        }))
     }
 }
 
@@ -42,13 +127,53 @@ impl Command for BookInfoCommand {
     type Page = String;
     type RequestParams = ();
 
-    type PageContent = BookInfo;
+    /// `None` if `parse` returns `nil` for a book that does not exist.
+    type PageContent = Option<BookInfo>;
 
-    fn parse(&self, content: Self::Page) -> Result<Self::PageContent> {
-        Ok(self.parse.call(content)?)
+    fn parse(&self, content: Self::Page, final_url: &str) -> Result<Parsed<Self::PageContent>> {
+        let info: Parsed<Option<BookInfo>> = self
+            .parse
+            .call((content, final_url))
+            .map_err(|e| CommandKind::BookInfo.parse_error(e))?;
+        Ok(info.map(|info| info.map(|info| self.normalize(info))))
     }
 
     fn page(&self, id: &str, _: Self::RequestParams) -> Result<Self::Request> {
         Ok(self.page.call(id)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cover() {
+        let lua = mlua::Lua::new();
+        let info = |cover: &str| {
+            lua.load(format!(
+                r#"{{
+                    title = "title",
+                    author = "author",
+                    cover = {},
+                    last_update = "last_update",
+                    status = "status",
+                    intro = "intro",
+                }}"#,
+                cover
+            ))
+            .eval::<BookInfo>()
+            .unwrap()
+        };
+        let cover = info(r#""https://example.com/cover.jpg""#).cover;
+        assert_eq!(cover, Cover::from("https://example.com/cover.jpg".to_string()));
+        assert_eq!(cover.thumb_or_full(), "https://example.com/cover.jpg");
+
+        let cover = info(r#"{thumb = "small.jpg", full = "large.jpg"}"#).cover;
+        assert_eq!(cover.thumb.as_deref(), Some("small.jpg"));
+        assert_eq!(cover.full, "large.jpg");
+        assert_eq!(cover.thumb_or_full(), "small.jpg");
+        let cover = info(r#"{full = "large.jpg"}"#).cover;
+        assert_eq!(cover.thumb, None);
+    }
+}
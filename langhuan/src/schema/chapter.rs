@@ -1,49 +1,258 @@
-use mlua::{FromLua, Function, Lua, Table, Value};
+use mlua::{FromLua, Function, Lua, LuaSerdeExt, Table, Value};
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
-use super::{Command, HttpRequest};
+use super::{
+    Command, CommandKind, HttpRequest, PageInfo, PageParams, Paginated, Parsed, normalize_text,
+};
 use crate::Result;
 
 #[derive(Debug)]
 pub struct ChapterCommand {
     page: Function,
     parse: Function,
+    pub(super) normalize_text: bool,
 }
 
-#[derive(Debug)]
+/// Serialized in the same `{ "type": ..., "content": ... }` shape the parse function returns.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "content", rename_all = "lowercase")]
 pub enum Paragraph {
     Text(String),
-    Image(String),
+    Image {
+        url: String,
+        alt: Option<String>,
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    /// text with inline formatting, see [`RichText`]
+    Rich(RichText),
+}
+
+/// A run of text sharing the same formatting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub text: String,
+    #[serde(default)]
+    pub bold: bool,
+    #[serde(default)]
+    pub italic: bool,
+    /// the reading annotation of the text, e.g. furigana
+    #[serde(default)]
+    pub ruby: Option<String>,
+}
+
+/// A paragraph with inline formatting, returned by a parse function as
+/// `{ type = "rich", content = { span, ... } }`.
+///
+/// A span is either a plain string or a table such as `{ text = "漢字", ruby = "かんじ" }`
+/// with the optional `bold`, `italic` and `ruby` fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct RichText {
+    pub spans: Vec<Span>,
+}
+
+impl RichText {
+    /// The text without its formatting, for readers that do not render it.
+    pub fn plain_text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+}
+
+impl FromLua for RichText {
+    fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
+        let spans: Vec<Value> = lua.unpack(value)?;
+        let spans = spans
+            .into_iter()
+            .map(|span| match span {
+                Value::String(text) => Ok(Span {
+                    text: text.to_str()?.to_string(),
+                    ..Default::default()
+                }),
+                span => lua.from_value(span),
+            })
+            .collect::<mlua::Result<_>>()?;
+        Ok(RichText { spans })
+    }
 }
 
 impl FromLua for Paragraph {
     fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
         let table: Table = lua.unpack(value)?;
         let r#type: String = table.get("type")?;
-        let content: String = table.get("content")?;
         match r#type.as_str() {
-            "text" => Ok(Paragraph::Text(content)),
-            "image" => Ok(Paragraph::Image(content)),
+            "text" => Ok(Paragraph::Text(table.get("content")?)),
+            // the content of an image is either its url or a table of the image fields
+            "image" => match table.get("content")? {
+                Value::Table(image) => Ok(Paragraph::Image {
+                    url: image.get("url")?,
+                    alt: image.get("alt")?,
+                    width: image.get("width")?,
+                    height: image.get("height")?,
+                }),
+                content => Ok(Paragraph::Image {
+                    url: lua.unpack(content)?,
+                    alt: None,
+                    width: None,
+                    height: None,
+                }),
+            },
+            "rich" => Ok(Paragraph::Rich(table.get("content")?)),
             _ => Err(mlua::Error::external("unknown paragraph type")),
         }
     }
 }
 
+/// The paragraphs of a chapter, e.g. collected from a [`ParagraphIter`] with
+/// `collect::<Result<ChapterContent>>()`.
+#[derive(Debug, Default, Serialize)]
+pub struct ChapterContent {
+    pub paragraphs: Vec<Paragraph>,
+}
+
+impl ChapterContent {
+    /// The urls of the images in the chapter, in order, so that they can be prefetched with
+    /// [`HttpClient::request_image`] while the text is displayed.
+    ///
+    /// [`HttpClient::request_image`]: crate::http::HttpClient::request_image
+    pub fn image_urls(&self) -> Vec<String> {
+        self.paragraphs
+            .iter()
+            .filter_map(|paragraph| match paragraph {
+                Paragraph::Image { url, .. } => Some(url.clone()),
+                Paragraph::Text(_) | Paragraph::Rich(_) => None,
+            })
+            .collect()
+    }
+
+    /// Split the text into pages of at most `chars_per_page` characters, e.g. for e-ink
+    /// readers.
+    ///
+    /// Paragraphs are joined with line breaks and a paragraph that does not fit on the
+    /// current page starts the next one. Only a paragraph longer than a whole page is split,
+    /// after the last space or sentence end that fits. Every image is a page of its own,
+    /// holding the marker `[image: <url>]`.
+    pub fn paginate(&self, chars_per_page: usize) -> Vec<String> {
+        let chars_per_page = chars_per_page.max(1);
+        let mut pages = Vec::new();
+        let mut page = String::new();
+        let mut page_chars = 0;
+        for paragraph in &self.paragraphs {
+            let text = match paragraph {
+                Paragraph::Text(text) => text.clone(),
+                Paragraph::Rich(text) => text.plain_text(),
+                Paragraph::Image { url, .. } => {
+                    if !page.is_empty() {
+                        pages.push(std::mem::take(&mut page));
+                        page_chars = 0;
+                    }
+                    pages.push(format!("[image: {}]", url));
+                    continue;
+                }
+            };
+            for chunk in split_paragraph(&text, chars_per_page) {
+                let chars = chunk.chars().count();
+                if !page.is_empty() && page_chars + 1 + chars > chars_per_page {
+                    pages.push(std::mem::take(&mut page));
+                    page_chars = 0;
+                }
+                if !page.is_empty() {
+                    page.push('\n');
+                    page_chars += 1;
+                }
+                page.push_str(chunk);
+                page_chars += chars;
+            }
+        }
+        if !page.is_empty() {
+            pages.push(page);
+        }
+        pages
+    }
+}
+
+/// Split a paragraph into chunks of at most `max_chars` characters, preferably after a
+/// space or a sentence end.
+fn split_paragraph(text: &str, max_chars: usize) -> Vec<&str> {
+    let is_boundary = |c: char| c.is_whitespace() || ".!?。！？…".contains(c);
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+    while rest.chars().count() > max_chars {
+        let limit = rest
+            .char_indices()
+            .nth(max_chars)
+            .map_or(rest.len(), |(index, _)| index);
+        let end = rest[..limit]
+            .char_indices()
+            .filter(|(_, c)| is_boundary(*c))
+            .map(|(index, c)| index + c.len_utf8())
+            .next_back()
+            .unwrap_or(limit);
+        chunks.push(rest[..end].trim_end());
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+impl FromIterator<Paragraph> for ChapterContent {
+    fn from_iter<I: IntoIterator<Item = Paragraph>>(iter: I) -> Self {
+        ChapterContent {
+            paragraphs: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// What a chapter `parse` returns: a function emitting paragraphs, or the whole text.
+enum Paragraphs {
+    Parser(Function),
+    Text(Option<String>),
+}
+
+impl FromLua for Paragraphs {
+    fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
+        match value {
+            Value::String(text) => Ok(Paragraphs::Text(Some(text.to_str()?.to_string()))),
+            value => Function::from_lua(value, lua).map(Paragraphs::Parser),
+        }
+    }
+}
+
 pub struct ParagraphIter {
-    parse_fn: Function,
+    paragraphs: Paragraphs,
+    page_info: PageInfo,
+    normalize_text: bool,
+}
+
+impl Paginated for ParagraphIter {
+    fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
 }
 
 impl Iterator for ParagraphIter {
     type Item = Result<Paragraph>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.parse_fn
-            .call(())
-            .map_err(|e| {
-                error!("parse paragraph failed: {}", e);
-                e.into()
-            })
-            .transpose()
+        let paragraph = match &mut self.paragraphs {
+            Paragraphs::Parser(parse_fn) => parse_fn
+                .call(())
+                .map_err(|e| {
+                    error!("parse paragraph failed: {}", e);
+                    e.into()
+                })
+                .transpose(),
+            Paragraphs::Text(text) => text.take().map(|text| Ok(Paragraph::Text(text))),
+        };
+        match paragraph {
+            Some(Ok(Paragraph::Text(text))) if self.normalize_text => {
+                Some(Ok(Paragraph::Text(normalize_text(&text))))
+            }
+            paragraph => paragraph,
+        }
     }
 }
 
@@ -52,23 +261,170 @@ impl FromLua for ChapterCommand {
         let table: Table = lua.unpack(value)?;
         let page = table.get("page")?;
         let parse = table.get("parse")?;
-        Ok(ChapterCommand { page, parse })
+        Ok(ChapterCommand {
+            page,
+            parse,
+            normalize_text: false,
+        })
     }
 }
 
 impl Command for ChapterCommand {
     type Request = Option<HttpRequest>;
     type Page = String;
-    type RequestParams = (u64, Option<Self::Page>);
+    type RequestParams = PageParams;
     type PageContent = ParagraphIter;
 
     fn page(&self, id: &str, params: Self::RequestParams) -> Result<Self::Request> {
-        let page: Self::Request = self.page.call((id, params.0, params.1))?;
+        let page: Self::Request = self.page.call((id, params.page, params.content, params.cursor))?;
         Ok(page)
     }
 
-    fn parse(&self, content: Self::Page) -> Result<Self::PageContent> {
-        let content: Function = self.parse.call(content)?;
-        Ok(ParagraphIter { parse_fn: content })
+    fn parse(&self, content: Self::Page, final_url: &str) -> Result<Parsed<Self::PageContent>> {
+        let (content, page_info): (Parsed<Paragraphs>, PageInfo) = self
+            .parse
+            .call((content, final_url))
+            .map_err(|e| CommandKind::Chapter.parse_error(e))?;
+        Ok(content.map(|paragraphs| ParagraphIter {
+            paragraphs,
+            page_info,
+            normalize_text: self.normalize_text,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_paragraph() {
+        let lua = mlua::Lua::new();
+        let paragraph: Paragraph = lua
+            .load(r#"return {type = "image", content = "https://example.com/1.png"}"#)
+            .eval()
+            .unwrap();
+        assert!(matches!(
+            paragraph,
+            Paragraph::Image { url, alt: None, width: None, height: None }
+                if url == "https://example.com/1.png"
+        ));
+
+        let paragraph: Paragraph = lua
+            .load(
+                r#"return {
+                    type = "image",
+                    content = {url = "https://example.com/1.png", alt = "map", width = 640},
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        assert!(matches!(
+            paragraph,
+            Paragraph::Image { url, alt: Some(alt), width: Some(640), height: None }
+                if url == "https://example.com/1.png" && alt == "map"
+        ));
+    }
+
+    #[test]
+    fn test_rich_text() {
+        let lua = mlua::Lua::new();
+        let paragraph: Paragraph = lua
+            .load(
+                r#"return {
+                    type = "rich",
+                    content = {
+                        "plain ",
+                        {text = "bold", bold = true},
+                        {text = "漢字", ruby = "かんじ", italic = true},
+                    },
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        let text = match paragraph {
+            Paragraph::Rich(text) => text,
+            other => panic!("unexpected paragraph: {:?}", other),
+        };
+        assert_eq!(
+            text.spans,
+            [
+                Span {
+                    text: "plain ".to_string(),
+                    ..Default::default()
+                },
+                Span {
+                    text: "bold".to_string(),
+                    bold: true,
+                    ..Default::default()
+                },
+                Span {
+                    text: "漢字".to_string(),
+                    italic: true,
+                    ruby: Some("かんじ".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(text.plain_text(), "plain bold漢字");
+
+        // the simple text paragraph is untouched
+        let paragraph: Paragraph = lua
+            .load(r#"return {type = "text", content = "plain"}"#)
+            .eval()
+            .unwrap();
+        assert!(matches!(paragraph, Paragraph::Text(text) if text == "plain"));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let text = |text: &str| Paragraph::Text(text.to_string());
+        let content: ChapterContent = [
+            text("first paragraph"),
+            text("second"),
+            text("third paragraph"),
+            Paragraph::Image {
+                url: "https://example.com/1.png".to_string(),
+                alt: None,
+                width: None,
+                height: None,
+            },
+            text("a long paragraph. It is split after a sentence"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            content.paginate(25),
+            [
+                "first paragraph\nsecond",
+                "third paragraph",
+                "[image: https://example.com/1.png]",
+                "a long paragraph. It is",
+                "split after a sentence",
+            ]
+        );
+        assert_eq!(split_paragraph("一二三四五六", 4), ["一二三四", "五六"]);
+    }
+
+    #[test]
+    fn test_image_urls() {
+        let lua = mlua::Lua::new();
+        let paragraphs: Vec<Paragraph> = lua
+            .load(
+                r#"return {
+                    {type = "text", content = "first"},
+                    {type = "image", content = "https://example.com/1.png"},
+                    {type = "text", content = "second"},
+                    {type = "image", content = {url = "https://example.com/2.png", alt = "map"}},
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        let content: ChapterContent = paragraphs.into_iter().collect();
+        assert_eq!(content.paragraphs.len(), 4);
+        assert_eq!(
+            content.image_urls(),
+            ["https://example.com/1.png", "https://example.com/2.png"]
+        );
     }
 }
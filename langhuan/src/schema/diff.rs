@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use super::{BookInfo, TocItem};
+
+impl BookInfo {
+    /// The names of the fields that differ from `other`, e.g. to tell a refetched book
+    /// apart from the stored one.
+    pub fn diff(&self, other: &BookInfo) -> Vec<&'static str> {
+        let fields = [
+            ("title", self.title != other.title),
+            ("author", self.author != other.author),
+            ("cover", self.cover != other.cover),
+            ("last_update", self.last_update != other.last_update),
+            ("status", self.status != other.status),
+            ("intro", self.intro != other.intro),
+            ("chapter_count", self.chapter_count != other.chapter_count),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(field, _)| field)
+            .collect()
+    }
+}
+
+/// The chapters that changed between two snapshots of a table of contents, see [`toc_diff`].
+#[derive(Debug, Default)]
+pub struct TocDiff<'a> {
+    /// in the order of the new toc
+    pub added: Vec<&'a TocItem>,
+    /// in the order of the old toc
+    pub removed: Vec<&'a TocItem>,
+    /// the old and the new item of the chapters whose title or tags changed
+    pub changed: Vec<(&'a TocItem, &'a TocItem)>,
+}
+
+impl TocDiff<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two snapshots of a table of contents, matching the chapters by id.
+pub fn toc_diff<'a>(old: &'a [TocItem], new: &'a [TocItem]) -> TocDiff<'a> {
+    let old_items: HashMap<&str, &TocItem> =
+        old.iter().map(|item| (item.id.as_str(), item)).collect();
+    let new_items: HashMap<&str, &TocItem> =
+        new.iter().map(|item| (item.id.as_str(), item)).collect();
+    let mut diff = TocDiff::default();
+    for item in new {
+        match old_items.get(item.id.as_str()) {
+            None => diff.added.push(item),
+            Some(&old) if old.title != item.title || old.tags != item.tags => {
+                diff.changed.push((old, item))
+            }
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .iter()
+        .filter(|item| !new_items.contains_key(item.id.as_str()))
+        .collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, title: &str) -> TocItem {
+        TocItem {
+            title: title.to_string(),
+            id: id.to_string(),
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_book_info_diff() {
+        let info = || BookInfo {
+            title: "title".to_string(),
+            author: "author".to_string(),
+            cover: "cover".to_string().into(),
+            last_update: "2024-01-01".to_string(),
+            status: "ongoing".to_string(),
+            intro: "intro".to_string(),
+            chapter_count: Some(10),
+        };
+        assert!(info().diff(&info()).is_empty());
+        let updated = BookInfo {
+            last_update: "2024-02-01".to_string(),
+            chapter_count: Some(12),
+            ..info()
+        };
+        assert_eq!(info().diff(&updated), vec!["last_update", "chapter_count"]);
+    }
+
+    #[test]
+    fn test_toc_diff() {
+        let old = vec![item("1", "one"), item("2", "two"), item("3", "three")];
+        let new = vec![
+            item("1", "one"),
+            item("3", "three (revised)"),
+            item("4", "four"),
+            item("5", "five"),
+        ];
+        let diff = toc_diff(&old, &new);
+        let ids = |items: &[&TocItem]| items.iter().map(|item| item.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&diff.added), vec!["4", "5"]);
+        assert_eq!(ids(&diff.removed), vec!["2"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0.title, "three");
+        assert_eq!(diff.changed[0].1.title, "three (revised)");
+
+        assert!(toc_diff(&old, &old).is_empty());
+    }
+}
@@ -40,7 +40,7 @@ fn parse_whitespace_line(input: &str) -> IResult<&str, ()> {
     Ok((input, ()))
 }
 
-fn parse_line(input: &str) -> IResult<&str, Line> {
+fn parse_line(input: &str) -> IResult<&str, Line<'_>> {
     if let Ok((input, _)) = parse_whitespace_line(input) {
         return Ok((input, Line::Whitespace));
     }
@@ -0,0 +1,110 @@
+/// Clean up text scraped from html.
+///
+/// Whitespace (including NBSP) is collapsed to single spaces, zero-width and control
+/// characters are removed, and runs of blank lines are reduced to a single blank line.
+pub fn normalize_text(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut pending_blank = false;
+    for line in text.lines() {
+        let line: String = line
+            .chars()
+            .filter_map(|c| match c {
+                '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}' | '\u{feff}' => None,
+                c if c.is_whitespace() => Some(' '),
+                c if c.is_control() => None,
+                c => Some(c),
+            })
+            .collect();
+        let mut words = line.split(' ').filter(|word| !word.is_empty()).peekable();
+        if words.peek().is_none() {
+            pending_blank = !normalized.is_empty();
+            continue;
+        }
+        if !normalized.is_empty() {
+            normalized.push('\n');
+            if pending_blank {
+                normalized.push('\n');
+            }
+        }
+        pending_blank = false;
+        for (index, word) in words.enumerate() {
+            if index > 0 {
+                normalized.push(' ');
+            }
+            normalized.push_str(word);
+        }
+    }
+    normalized
+}
+
+/// How the tags of toc items are cleaned up, see [`normalize_tags`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagNormalization {
+    pub lowercase: bool,
+    /// the most tags kept per item
+    pub max_tags: usize,
+}
+
+impl Default for TagNormalization {
+    fn default() -> Self {
+        Self {
+            lowercase: false,
+            max_tags: 8,
+        }
+    }
+}
+
+/// Trim the tags, drop empty and duplicate ones, keeping the first occurrence, and keep at
+/// most `max_tags` of them.
+pub fn normalize_tags(tags: Vec<String>, normalization: &TagNormalization) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        if normalized.len() == normalization.max_tags {
+            break;
+        }
+        let tag = tag.trim();
+        let tag = if normalization.lowercase {
+            tag.to_lowercase()
+        } else {
+            tag.to_string()
+        };
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("a\u{a0}\u{a0}b"), "a b");
+        assert_eq!(normalize_text("a\u{200b}b\u{feff}"), "ab");
+        assert_eq!(normalize_text("  a \t b\r\n"), "a b");
+        assert_eq!(normalize_text("a\u{7}b"), "ab");
+        assert_eq!(normalize_text("\n\na\n\n\n\nb\n\n"), "a\n\nb");
+        assert_eq!(normalize_text("a\nb"), "a\nb");
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        let tags = || {
+            [" Fantasy", "fantasy ", "Fantasy", "", "  ", "Magic", "Dragons"]
+                .map(|tag| tag.to_string())
+                .to_vec()
+        };
+        let normalization = TagNormalization::default();
+        assert_eq!(
+            normalize_tags(tags(), &normalization),
+            ["Fantasy", "fantasy", "Magic", "Dragons"]
+        );
+        let normalization = TagNormalization {
+            lowercase: true,
+            max_tags: 2,
+        };
+        assert_eq!(normalize_tags(tags(), &normalization), ["fantasy", "magic"]);
+    }
+}
@@ -1,8 +1,8 @@
 use mlua::{FromLua, Function, Lua, LuaSerdeExt, Table, Value};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
-use super::{Command, HttpRequest};
+use super::{Command, CommandKind, HttpRequest, PageInfo, PageParams, Paginated, Parsed};
 use crate::Result;
 
 #[derive(Debug)]
@@ -11,7 +11,7 @@ pub struct SearchCommand {
     parse: Function,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SearchItem {
     pub id: String,
     pub title: String,
@@ -20,6 +20,32 @@ pub struct SearchItem {
     pub last_update: String,
     pub status: String,
     pub intro: String,
+    #[serde(default)]
+    pub rating: Option<f64>,
+    #[serde(default)]
+    pub word_count: Option<u64>,
+    #[serde(default)]
+    pub latest_chapter: Option<String>,
+    /// the context the keyword matched in
+    #[serde(default)]
+    pub snippet: Option<String>,
+    /// the id of the schema that found the item, set by [`SchemaRegistry::search_all`]
+    ///
+    /// [`SchemaRegistry::search_all`]: crate::registry::SchemaRegistry::search_all
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+impl SearchItem {
+    /// The snippet, or the intro if there is none, with every occurrence of the keyword
+    /// wrapped in `open` and `close`, e.g. `<em>` and `</em>`.
+    pub fn highlight(&self, keyword: &str, open: &str, close: &str) -> String {
+        let text = self.snippet.as_deref().unwrap_or(&self.intro);
+        if keyword.is_empty() {
+            return text.to_string();
+        }
+        text.replace(keyword, &format!("{}{}{}", open, keyword, close))
+    }
 }
 
 impl FromLua for SearchItem {
@@ -30,6 +56,13 @@ impl FromLua for SearchItem {
 
 pub struct SearchItemIter {
     parse_fn: Function,
+    page_info: PageInfo,
+}
+
+impl Paginated for SearchItemIter {
+    fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
 }
 
 impl Iterator for SearchItemIter {
@@ -58,17 +91,23 @@ impl FromLua for SearchCommand {
 impl Command for SearchCommand {
     type Request = Option<HttpRequest>;
     type Page = String;
-    type RequestParams = (u64, Option<Self::Page>);
+    type RequestParams = PageParams;
     type PageContent = SearchItemIter;
 
     fn page(&self, id: &str, params: Self::RequestParams) -> Result<Self::Request> {
-        let page: Self::Request = self.page.call((id, params.0, params.1))?;
+        let page: Self::Request = self.page.call((id, params.page, params.content, params.cursor))?;
         Ok(page)
     }
 
-    fn parse(&self, content: Self::Page) -> Result<Self::PageContent> {
-        let content: Function = self.parse.call(content)?;
-        Ok(SearchItemIter { parse_fn: content })
+    fn parse(&self, content: Self::Page, final_url: &str) -> Result<Parsed<Self::PageContent>> {
+        let (content, page_info): (Parsed<Function>, PageInfo) = self
+            .parse
+            .call((content, final_url))
+            .map_err(|e| CommandKind::Search.parse_error(e))?;
+        Ok(content.map(|parse_fn| SearchItemIter {
+            parse_fn,
+            page_info,
+        }))
     }
 }
 
@@ -79,6 +118,81 @@ mod tests {
     use crate::http::HttpClient;
     use crate::schema::PageItems;
 
+    #[test]
+    fn test_search_item_optional_fields() {
+        let lua = Lua::new();
+        let item = lua
+            .load(
+                r#"
+                {
+                    id = "1",
+                    title = "title",
+                    author = "author",
+                    cover = "cover",
+                    last_update = "last_update",
+                    status = "status",
+                    intro = "intro",
+                    rating = 4.5,
+                    word_count = 123456,
+                    latest_chapter = "chapter 100",
+                }
+            "#,
+            )
+            .eval::<SearchItem>()
+            .unwrap();
+        assert_eq!(item.rating, Some(4.5));
+        assert_eq!(item.word_count, Some(123456));
+        assert_eq!(item.latest_chapter.as_deref(), Some("chapter 100"));
+
+        let item = lua
+            .load(
+                r#"
+                {
+                    id = "1",
+                    title = "title",
+                    author = "author",
+                    cover = "cover",
+                    last_update = "last_update",
+                    status = "status",
+                    intro = "intro",
+                }
+            "#,
+            )
+            .eval::<SearchItem>()
+            .unwrap();
+        assert_eq!(item.rating, None);
+        assert_eq!(item.word_count, None);
+        assert_eq!(item.latest_chapter, None);
+        assert_eq!(item.snippet, None);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let lua = Lua::new();
+        let item = lua
+            .load(
+                r#"
+                {
+                    id = "1",
+                    title = "title",
+                    author = "author",
+                    cover = "cover",
+                    last_update = "last_update",
+                    status = "status",
+                    intro = "intro",
+                    snippet = "a dragon meets another dragon",
+                }
+            "#,
+            )
+            .eval::<SearchItem>()
+            .unwrap();
+        assert_eq!(
+            item.highlight("dragon", "<em>", "</em>"),
+            "a <em>dragon</em> meets another <em>dragon</em>"
+        );
+        assert_eq!(item.highlight("", "<em>", "</em>"), "a dragon meets another dragon");
+    }
+
     #[tokio::test]
     async fn test_search() {
         let lua = Lua::new();
@@ -115,13 +229,7 @@ mod tests {
             )
             .eval::<SearchCommand>();
         let search = search.unwrap();
-        let mut items = PageItems {
-            command: &search,
-            id: "keyword",
-            page: 1,
-            page_content: None,
-            http: &http,
-        };
+        let mut items = PageItems::new(&search, "keyword", &http);
         let item = items
             .next_page()
             .await
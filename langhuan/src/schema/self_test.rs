@@ -0,0 +1,70 @@
+use mlua::{FromLua, LuaSerdeExt};
+use serde::Deserialize;
+
+use super::CommandKind;
+
+/// The samples declared in the `tests` table of a schema.
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct SelfTests {
+    #[serde(default)]
+    pub(super) book_info: Vec<BookInfoSample>,
+    #[serde(default)]
+    pub(super) search: Vec<SearchSample>,
+}
+
+impl FromLua for SelfTests {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Nil => Ok(SelfTests::default()),
+            value => lua.from_value(value),
+        }
+    }
+}
+
+/// A book whose info is expected to have the title.
+#[derive(Debug, Deserialize)]
+pub(super) struct BookInfoSample {
+    pub(super) id: String,
+    pub(super) title: String,
+}
+
+/// A keyword whose first search result is expected to have the title.
+#[derive(Debug, Deserialize)]
+pub(super) struct SearchSample {
+    pub(super) keyword: String,
+    pub(super) title: String,
+}
+
+/// The outcome of one sample of a self test.
+#[derive(Debug)]
+pub struct TestCase {
+    pub command: CommandKind,
+    /// the id or keyword of the sample
+    pub input: String,
+    /// why the sample failed, `None` if it passed
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct TestReport {
+    pub cases: Vec<TestCase>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(|case| case.failure.is_none())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &TestCase> {
+        self.cases.iter().filter(|case| case.failure.is_some())
+    }
+}
+
+/// Compare an actual title with the expected one, describing a mismatch.
+pub(super) fn check_title(expected: &str, actual: Option<&str>) -> Option<String> {
+    match actual {
+        Some(actual) if actual == expected => None,
+        Some(actual) => Some(format!("expected title {:?}, got {:?}", expected, actual)),
+        None => Some(format!("expected title {:?}, got no result", expected)),
+    }
+}
@@ -1,6 +1,6 @@
 use mlua::{FromLua, Function};
 
-use super::{Command, HttpRequest};
+use super::{Command, HttpRequest, HttpResponse, LuaRequest, Parsed};
 
 use crate::Result;
 
@@ -11,6 +11,8 @@ pub struct SessionCommand {
     page: Function,
     parse: Function,
     wrap: Function,
+    /// build each further login request from the response to the previous one
+    pub(super) steps: Vec<Function>,
 }
 
 impl SessionCommand {
@@ -19,7 +21,32 @@ impl SessionCommand {
         page_path: <Self as Command>::Request,
         session: <Self as Command>::PageContent,
     ) -> Result<<Self as Command>::Request> {
-        Ok(self.wrap.call((page_path, session))?)
+        self.wrap_with(page_path, session, true)
+    }
+
+    /// Wrap the request, passing its absent fields to Lua as `null` if `none_as_null` is
+    /// set, or leaving them `nil` otherwise.
+    pub fn wrap_with(
+        &self,
+        page_path: <Self as Command>::Request,
+        session: <Self as Command>::PageContent,
+        none_as_null: bool,
+    ) -> Result<<Self as Command>::Request> {
+        let request = LuaRequest {
+            request: page_path,
+            none_as_null,
+        };
+        Ok(self.wrap.call((request, session))?)
+    }
+
+    /// Parse the login response, passing its headers to `parse` as the second argument.
+    pub fn parse_response(&self, response: HttpResponse) -> Result<Parsed<Session>> {
+        Ok(self.parse.call((response.body, response.headers))?)
+    }
+
+    /// Build the request of a login step from the response to the previous request.
+    pub(super) fn step(&self, step: &Function, response: HttpResponse) -> Result<HttpRequest> {
+        Ok(step.call((response.body, response.headers))?)
     }
 }
 
@@ -29,7 +56,13 @@ impl FromLua for SessionCommand {
         let page = table.get("page")?;
         let parse = table.get("parse")?;
         let wrap = table.get("wrap")?;
-        Ok(SessionCommand { page, parse, wrap })
+        let steps: Option<Vec<Function>> = table.get("steps")?;
+        Ok(SessionCommand {
+            page,
+            parse,
+            wrap,
+            steps: steps.unwrap_or_default(),
+        })
     }
 }
 
@@ -41,7 +74,7 @@ impl Command for SessionCommand {
 
     type PageContent = Session;
 
-    fn parse(&self, content: Self::Page) -> Result<Self::PageContent> {
+    fn parse(&self, content: Self::Page, _: &str) -> Result<Parsed<Self::PageContent>> {
         Ok(self.parse.call(content)?)
     }
 
@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::HttpRequest;
+use crate::{Result, SchemaError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignAlgorithm {
+    HmacSha256,
+}
+
+/// The signature declared by `--@sign: <algorithm> <param> [field,...]`.
+///
+/// The query params named by the fields, or all of them if none are listed, are sorted by
+/// name and joined as `name=value&...`; the hex encoded hmac of that string is appended to
+/// the query as `param`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignScheme {
+    pub algorithm: SignAlgorithm,
+    pub param: String,
+    pub fields: Option<Vec<String>>,
+}
+
+impl FromStr for SignScheme {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || crate::Error::ScriptParseError(format!("invalid sign: {}", s));
+        let mut parts = s.split_whitespace();
+        let algorithm = match parts.next() {
+            Some("hmac-sha256") => SignAlgorithm::HmacSha256,
+            _ => return Err(invalid()),
+        };
+        let param = parts.next().ok_or_else(invalid)?.to_string();
+        let fields = parts
+            .next()
+            .map(|fields| fields.split(',').map(|field| field.to_string()).collect());
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(SignScheme {
+            algorithm,
+            param,
+            fields,
+        })
+    }
+}
+
+impl SignScheme {
+    /// Append the signature of the request's query to its url.
+    pub fn sign(&self, mut request: HttpRequest, secret: &[u8]) -> Result<HttpRequest> {
+        let mut url = url::Url::parse(&request.url)
+            .map_err(|e| SchemaError::InvalidUrl(format!("{} for {}", e, request.url)))?;
+        let mut params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| match &self.fields {
+                Some(fields) => fields.iter().any(|field| field == name),
+                None => true,
+            })
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        params.sort();
+        let message = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+        let signature = match self.algorithm {
+            SignAlgorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+                    .expect("hmac accepts keys of any length");
+                mac.update(message.as_bytes());
+                mac.finalize().into_bytes()
+            }
+        };
+        let signature: String = signature.iter().map(|byte| format!("{:02x}", byte)).collect();
+        url.query_pairs_mut().append_pair(&self.param, &signature);
+        request.url = url.into();
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        let request = || HttpRequest {
+            url: "https://www.example.com/search?q=abc&page=1&ts=100".to_string(),
+            ..Default::default()
+        };
+        let scheme: SignScheme = "hmac-sha256 sign".parse().unwrap();
+        let signed = scheme.sign(request(), b"secret").unwrap();
+        assert_eq!(
+            signed.url,
+            "https://www.example.com/search?q=abc&page=1&ts=100\
+             &sign=2f89c6720d2dc21100884166ee0c0c1fec30fd6869a3d83932cdeb9dedc4f230"
+        );
+
+        let scheme: SignScheme = "hmac-sha256 sign q".parse().unwrap();
+        assert_eq!(scheme.fields, Some(vec!["q".to_string()]));
+        let signed = scheme.sign(request(), b"secret").unwrap();
+        assert!(
+            signed
+                .url
+                .ends_with("&sign=026ab0f08edc7ac550db5ed05411ce6ba0e7b9ea034978428fdf6bc9813d4b4b")
+        );
+
+        assert!("md5 sign".parse::<SignScheme>().is_err());
+        assert!("hmac-sha256".parse::<SignScheme>().is_err());
+    }
+}
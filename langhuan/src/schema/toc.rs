@@ -1,17 +1,22 @@
 use mlua::{FromLua, Function, Lua, LuaSerdeExt, Table, Value};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
-use super::{Command, HttpRequest};
+use super::{
+    Command, CommandKind, HttpRequest, PageInfo, PageParams, Paginated, Parsed, TagNormalization,
+    normalize_tags,
+};
 use crate::Result;
 
 #[derive(Debug)]
 pub struct TocCommand {
     page: Function,
     parse: Function,
+    /// the tags are kept raw if `None`
+    pub(super) tag_normalization: Option<TagNormalization>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TocItem {
     pub title: String,
     pub id: String,
@@ -27,19 +32,45 @@ impl FromLua for TocItem {
 
 pub struct TocItemIter {
     parse_fn: Function,
+    page_info: PageInfo,
+    tag_normalization: Option<TagNormalization>,
+}
+
+impl TocItemIter {
+    pub(super) fn new(parse_fn: Function, tag_normalization: Option<TagNormalization>) -> Self {
+        Self {
+            parse_fn,
+            page_info: PageInfo::default(),
+            tag_normalization,
+        }
+    }
+}
+
+impl Paginated for TocItemIter {
+    fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
 }
 
 impl Iterator for TocItemIter {
     type Item = Result<TocItem>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.parse_fn
+        let item: Option<Result<TocItem>> = self
+            .parse_fn
             .call(())
             .map_err(|e| {
                 error!("search item failed: {}", e);
                 e.into()
             })
-            .transpose()
+            .transpose();
+        match (item, &self.tag_normalization) {
+            (Some(Ok(mut item)), Some(normalization)) => {
+                item.tags = normalize_tags(item.tags, normalization);
+                Some(Ok(item))
+            }
+            (item, _) => item,
+        }
     }
 }
 
@@ -48,23 +79,34 @@ impl FromLua for TocCommand {
         let table: Table = lua.unpack(value)?;
         let page = table.get("page")?;
         let parse = table.get("parse")?;
-        Ok(TocCommand { page, parse })
+        Ok(TocCommand {
+            page,
+            parse,
+            tag_normalization: None,
+        })
     }
 }
 
 impl Command for TocCommand {
     type Request = Option<HttpRequest>;
     type Page = String;
-    type RequestParams = (u64, Option<Self::Page>);
+    type RequestParams = PageParams;
     type PageContent = TocItemIter;
 
     fn page(&self, id: &str, params: Self::RequestParams) -> Result<Self::Request> {
-        let page: Self::Request = self.page.call((id, params.0, params.1))?;
+        let page: Self::Request = self.page.call((id, params.page, params.content, params.cursor))?;
         Ok(page)
     }
 
-    fn parse(&self, content: Self::Page) -> Result<Self::PageContent> {
-        let content: Function = self.parse.call(content)?;
-        Ok(TocItemIter { parse_fn: content })
+    fn parse(&self, content: Self::Page, final_url: &str) -> Result<Parsed<Self::PageContent>> {
+        let (content, page_info): (Parsed<Function>, PageInfo) = self
+            .parse
+            .call((content, final_url))
+            .map_err(|e| CommandKind::Toc.parse_error(e))?;
+        Ok(content.map(|parse_fn| TocItemIter {
+            parse_fn,
+            page_info,
+            tag_normalization: self.tag_normalization,
+        }))
     }
 }
@@ -0,0 +1,126 @@
+use super::{BookInfo, CommandKind, Paragraph, Parsed, SearchItem, TocItem};
+use crate::Result;
+
+/// The most items of a page that are validated, as a `parse` iterator may never end.
+const MAX_VALIDATED_ITEMS: usize = 1000;
+
+/// A problem with what a `parse` function produced.
+#[derive(Debug)]
+pub struct ValidationIssue {
+    /// the index of the item in the page, `None` for the page itself
+    pub item: Option<usize>,
+    /// the field at fault, `None` if the whole item or page is
+    pub field: Option<&'static str>,
+    pub message: String,
+}
+
+/// The outcome of [`Schema::validate`].
+///
+/// [`Schema::validate`]: super::Schema::validate
+#[derive(Debug)]
+pub struct ValidationReport {
+    pub command: CommandKind,
+    /// the number of items parsed from the page
+    pub items: usize,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub(super) fn new(command: CommandKind) -> Self {
+        Self {
+            command,
+            items: 0,
+            issues: Vec::new(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn issue(&mut self, item: Option<usize>, field: Option<&'static str>, message: String) {
+        self.issues.push(ValidationIssue {
+            item,
+            field,
+            message,
+        });
+    }
+
+    fn check_fields(&mut self, item: Option<usize>, fields: Vec<(&'static str, &str)>) {
+        for (field, value) in fields {
+            if value.trim().is_empty() {
+                self.issue(item, Some(field), format!("{} is empty", field));
+            }
+        }
+    }
+
+    /// Check the single value parsed from a page, which `parse` may leave out as not found.
+    pub(super) fn check_value<T>(
+        &mut self,
+        parsed: Result<Parsed<Option<T>>>,
+        fields: fn(&T) -> Vec<(&'static str, &str)>,
+    ) {
+        match parsed {
+            Ok(Parsed::Content(Some(value))) => {
+                self.items = 1;
+                self.check_fields(None, fields(&value));
+            }
+            Ok(Parsed::Content(None)) => self.issue(
+                None,
+                None,
+                "parse returned nil, as for a page that was not found".to_string(),
+            ),
+            Ok(Parsed::Refetch(_)) => self.refetched(),
+            Err(e) => self.issue(None, None, e.to_string()),
+        }
+    }
+
+    /// Check every item parsed from a page.
+    pub(super) fn check_items<I, T>(
+        &mut self,
+        parsed: Result<Parsed<I>>,
+        fields: fn(&T) -> Vec<(&'static str, &str)>,
+    ) where
+        I: Iterator<Item = Result<T>>,
+    {
+        let items = match parsed {
+            Ok(Parsed::Content(items)) => items,
+            Ok(Parsed::Refetch(_)) => return self.refetched(),
+            Err(e) => return self.issue(None, None, e.to_string()),
+        };
+        for (index, item) in items.take(MAX_VALIDATED_ITEMS).enumerate() {
+            self.items += 1;
+            match item {
+                Ok(item) => self.check_fields(Some(index), fields(&item)),
+                Err(e) => self.issue(Some(index), None, e.to_string()),
+            }
+        }
+    }
+
+    fn refetched(&mut self) {
+        self.issue(
+            None,
+            None,
+            "parse requested a refetch instead of parsing the page".to_string(),
+        );
+    }
+}
+
+pub(super) fn search_item_fields(item: &SearchItem) -> Vec<(&'static str, &str)> {
+    vec![("id", item.id.as_str()), ("title", item.title.as_str())]
+}
+
+pub(super) fn book_info_fields(info: &BookInfo) -> Vec<(&'static str, &str)> {
+    vec![("title", info.title.as_str())]
+}
+
+pub(super) fn toc_item_fields(item: &TocItem) -> Vec<(&'static str, &str)> {
+    vec![("id", item.id.as_str()), ("title", item.title.as_str())]
+}
+
+pub(super) fn paragraph_fields(paragraph: &Paragraph) -> Vec<(&'static str, &str)> {
+    match paragraph {
+        Paragraph::Text(_) | Paragraph::Rich(_) => Vec::new(),
+        Paragraph::Image { url, .. } => vec![("url", url.as_str())],
+    }
+}